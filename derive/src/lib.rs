@@ -0,0 +1,153 @@
+//! Derive macros for `pori`. Use the `derive` feature of `pori` rather than
+//! depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Field, Fields, Index, Member};
+
+struct Arm {
+    pattern: TokenStream2,
+    label: String,
+    bindings: Vec<TokenStream2>,
+    children: Vec<TokenStream2>,
+}
+
+impl Arm {
+    fn new(pattern: TokenStream2, label: String, fields: &Fields) -> syn::Result<Self> {
+        let mut bindings = Vec::new();
+        let mut children = Vec::new();
+        for (index, field) in fields.iter().enumerate() {
+            let child = match child(field)? {
+                Some(child) => child,
+                None => continue,
+            };
+            let member = match &field.ident {
+                Some(ident) => Member::Named(ident.clone()),
+                None => Member::Unnamed(Index::from(index)),
+            };
+            let binding = format_ident!("__child{}", index);
+            bindings.push(quote! { #member: #binding, });
+            children.push(match child {
+                Child::One => quote! {
+                    children.push(#binding as &dyn ::pori::DebugTree);
+                },
+                Child::Many => quote! {
+                    children.extend(
+                        ::std::iter::IntoIterator::into_iter(#binding)
+                            .map(|child| child as &dyn ::pori::DebugTree),
+                    );
+                },
+            });
+        }
+        Ok(Arm {
+            pattern,
+            label,
+            bindings,
+            children,
+        })
+    }
+}
+
+enum Child {
+    One,
+    Many,
+}
+
+/// Derives `DebugTree`.
+///
+/// The label of a struct is its name and the label of an enum is the name of
+/// its variant. Fields are children when annotated with
+/// `#[debug_tree(child)]`, in which case the field must implement `DebugTree`,
+/// or `#[debug_tree(children)]`, in which case a reference to the field must
+/// iterate over items that implement `DebugTree`, such as `Vec` or `Option`.
+#[proc_macro_derive(DebugTree, attributes(debug_tree))]
+pub fn derive_debug_tree(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn child(field: &Field) -> syn::Result<Option<Child>> {
+    let mut child = None;
+    for attribute in field
+        .attrs
+        .iter()
+        .filter(|attribute| attribute.path().is_ident("debug_tree"))
+    {
+        attribute.parse_nested_meta(|meta| {
+            if meta.path.is_ident("child") {
+                child = Some(Child::One);
+                Ok(())
+            }
+            else if meta.path.is_ident("children") {
+                child = Some(Child::Many);
+                Ok(())
+            }
+            else {
+                Err(meta.error("expected `child` or `children`"))
+            }
+        })?;
+    }
+    Ok(child)
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let arms = match &input.data {
+        Data::Struct(data) => vec![Arm::new(quote! { Self }, name.to_string(), &data.fields)?],
+        Data::Enum(data) => data
+            .variants
+            .iter()
+            .map(|variant| {
+                let ident = &variant.ident;
+                Arm::new(quote! { Self::#ident }, ident.to_string(), &variant.fields)
+            })
+            .collect::<syn::Result<_>>()?,
+        Data::Union(_) => {
+            return Err(Error::new_spanned(
+                &input,
+                "`DebugTree` cannot be derived for unions",
+            ));
+        }
+    };
+    let labels = arms.iter().map(|arm| {
+        let Arm { pattern, label, .. } = arm;
+        quote! {
+            #pattern { .. } => ::std::string::String::from(#label),
+        }
+    });
+    let children = arms.iter().map(|arm| {
+        let Arm {
+            pattern,
+            bindings,
+            children,
+            ..
+        } = arm;
+        quote! {
+            #pattern { #(#bindings)* .. } => {
+                #(#children)*
+            }
+        }
+    });
+    Ok(quote! {
+        impl #impl_generics ::pori::DebugTree for #name #type_generics #where_clause {
+            fn label(&self) -> ::std::string::String {
+                match self {
+                    #(#labels)*
+                }
+            }
+
+            fn children(&self) -> ::std::vec::Vec<&dyn ::pori::DebugTree> {
+                let mut children: ::std::vec::Vec<&dyn ::pori::DebugTree> =
+                    ::std::vec::Vec::new();
+                match self {
+                    #(#children)*
+                }
+                children
+            }
+        }
+    })
+}
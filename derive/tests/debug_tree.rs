@@ -0,0 +1,45 @@
+use pori::{debug_tree, DebugTree, Spanned};
+
+#[derive(DebugTree)]
+enum Expr {
+    Add(
+        #[debug_tree(child)] Box<Spanned<Expr>>,
+        #[debug_tree(child)] Box<Spanned<Expr>>,
+    ),
+    Call {
+        #[debug_tree(children)]
+        arguments: Vec<Spanned<Expr>>,
+    },
+    Number,
+}
+
+#[derive(DebugTree)]
+struct Program {
+    #[debug_tree(children)]
+    body: Option<Spanned<Expr>>,
+}
+
+#[test]
+fn derive_debug_tree() {
+    let expr = Spanned::new(
+        0..11,
+        Expr::Call {
+            arguments: vec![
+                Spanned::new(2..3, Expr::Number),
+                Spanned::new(
+                    5..10,
+                    Expr::Add(
+                        Box::new(Spanned::new(5..6, Expr::Number)),
+                        Box::new(Spanned::new(9..10, Expr::Number)),
+                    ),
+                ),
+            ],
+        },
+    );
+    assert_eq!(
+        debug_tree(&Program { body: Some(expr) }),
+        "Program\n  Call [0..11]\n    Number [2..3]\n    Add [5..10]\n      Number [5..6]\n      \
+         Number [9..10]\n",
+    );
+    assert_eq!(debug_tree(&Program { body: None }), "Program\n");
+}
@@ -0,0 +1,57 @@
+//! Checks invariants of `Located` under arbitrary inputs and splits.
+//!
+//! Run this target from the repository root with `cargo-fuzz` on a nightly
+//! toolchain:
+//!
+//! ```shell
+//! cargo +nightly fuzz run located
+//! ```
+//!
+//! The following invariants are guarded for each split:
+//!
+//! - Locations are non-decreasing as input is consumed.
+//! - The offset between an input and its remainder is the number of consumed
+//!   bytes.
+//! - Neither half of a split has a location beyond the base location plus the
+//!   length of the original input.
+
+#![no_main]
+
+use libfuzzer_sys::arbitrary::{self, Arbitrary};
+use libfuzzer_sys::fuzz_target;
+use pori::nom::{InputLength, InputTake, Offset};
+use pori::{Located, Location};
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    data: Vec<u8>,
+    base: u32,
+    splits: Vec<u16>,
+}
+
+fuzz_target!(|input: Input| {
+    let Input { data, base, splits } = input;
+    let base = base as usize;
+    let end = base + data.len();
+    let origin = Located::from((data.as_slice(), base));
+
+    let mut input = origin;
+    for split in splits {
+        let n = usize::from(split) % (input.input_len() + 1);
+        let (remaining, taken) = input.take_split(n);
+
+        assert!(remaining.location() >= input.location());
+        assert_eq!(taken.location(), input.location());
+        assert_eq!(input.offset(&remaining), n);
+        assert_eq!(origin.offset(&remaining), remaining.location() - base);
+        assert!(remaining.location() + remaining.input_len() <= end);
+        assert!(taken.location() + taken.input_len() <= end);
+        assert_eq!(taken.location() + taken.input_len(), remaining.location());
+
+        let taken = input.take(n);
+        assert_eq!(taken.location(), input.location());
+        assert!(taken.location() + taken.input_len() <= end);
+
+        input = remaining;
+    }
+});
@@ -1,148 +1,703 @@
-use nom::error::{Error as NomError, ErrorKind, ParseError};
+#[cfg(feature = "testing")]
+pub mod testing;
+
+use nom::bytes::complete::{
+    escaped_transform, tag, take_till, take_till1, take_while, take_while1,
+};
+use nom::character::complete::{char, digit0, digit1, line_ending};
+use nom::error::{
+    ContextError, Error as NomError, ErrorKind, FromExternalError, ParseError, VerboseError,
+};
+use nom::sequence::separated_pair;
 use nom::{
-    AsBytes, Compare, CompareResult, Err as ErrorMode, ExtendInto, IResult, InputIter, InputLength,
-    InputTake, InputTakeAtPosition, Needed, Offset, Parser, Slice,
+    AsBytes, AsChar, Compare, CompareResult, Err as ErrorMode, ExtendInto, IResult, InputIter,
+    InputLength, InputTake, InputTakeAtPosition, Needed, Offset, Parser, Slice,
 };
-use std::borrow::{Cow, ToOwned};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::borrow::{Borrow, Cow, ToOwned};
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::error::Error;
+#[cfg(unix)]
+use std::ffi::OsStr;
 use std::fmt::{self, Display, Formatter};
-use std::ops::{Deref, RangeFrom, RangeTo};
+use std::hash::{Hash, Hasher};
+#[cfg(feature = "serde")]
+use std::io::{self, Write};
+use std::iter;
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, Range, RangeFrom, RangeTo};
+#[cfg(unix)]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(unix)]
+use std::path::Path;
+use std::rc::Rc;
+use std::slice;
+#[cfg(feature = "unicode")]
+use unicode_segmentation::UnicodeSegmentation;
 
 pub use nom;
+#[cfg(feature = "derive")]
+pub use pori_derive::DebugTree;
 
-pub trait Location {
-    fn location(&self) -> usize;
+pub trait DebugTree {
+    fn label(&self) -> String;
+
+    fn span(&self) -> Option<Range<usize>> {
+        None
+    }
+
+    fn children(&self) -> Vec<&dyn DebugTree> {
+        Vec::new()
+    }
 }
 
-#[derive(Debug, Eq, Hash, PartialEq)]
-pub struct Located<'i, I>
+impl<T> DebugTree for Box<T>
 where
-    I: ?Sized,
+    T: DebugTree + ?Sized,
 {
-    data: &'i I,
-    location: usize,
+    fn label(&self) -> String {
+        (**self).label()
+    }
+
+    fn span(&self) -> Option<Range<usize>> {
+        (**self).span()
+    }
+
+    fn children(&self) -> Vec<&dyn DebugTree> {
+        (**self).children()
+    }
 }
 
-impl<'i, I> Located<'i, I>
+/// A sink of diagnostics, such as a renderer for a particular front end. See
+/// `report`.
+pub trait Diagnostics {
+    fn emit(&mut self, span: Range<usize>, severity: Severity, message: &str);
+}
+
+pub trait FullLocation: Location {
+    fn full_location(&self) -> (usize, usize, usize);
+}
+
+/// A choice of parsers that are paired with labels. See `alt_expected`.
+pub trait LabeledAlt<I, O, E> {
+    fn labels(&self) -> Vec<&'static str>;
+
+    fn choice(&mut self, input: I) -> IResult<I, (usize, O), E>;
+}
+
+macro_rules! impl_labeled_alt_for_tuple {
+    ($(($n:tt, $f:ident)),+$(,)?) => {
+        impl<I, O, E, $($f),+> LabeledAlt<I, O, E> for ($((&'static str, $f),)+)
+        where
+            I: Clone,
+            E: ParseError<I>,
+            $($f: Parser<I, O, E>,)+
+        {
+            fn labels(&self) -> Vec<&'static str> {
+                vec![$(self.$n.0),+]
+            }
+
+            fn choice(&mut self, input: I) -> IResult<I, (usize, O), E> {
+                let mut error: Option<E> = None;
+                $(
+                    match self.$n.1.parse(input.clone()) {
+                        Ok((remaining, output)) => return Ok((remaining, ($n, output))),
+                        Err(ErrorMode::Error(next)) => {
+                            error = Some(match error {
+                                Some(error) => error.or(next),
+                                None => next,
+                            });
+                        }
+                        Err(mode) => return Err(mode),
+                    }
+                )+
+                Err(ErrorMode::Error(E::append(input, ErrorKind::Alt, error.unwrap())))
+            }
+        }
+    };
+}
+impl_labeled_alt_for_tuple!((0, F0), (1, F1));
+impl_labeled_alt_for_tuple!((0, F0), (1, F1), (2, F2));
+impl_labeled_alt_for_tuple!((0, F0), (1, F1), (2, F2), (3, F3));
+impl_labeled_alt_for_tuple!((0, F0), (1, F1), (2, F2), (3, F3), (4, F4));
+impl_labeled_alt_for_tuple!((0, F0), (1, F1), (2, F2), (3, F3), (4, F4), (5, F5));
+impl_labeled_alt_for_tuple!(
+    (0, F0),
+    (1, F1),
+    (2, F2),
+    (3, F3),
+    (4, F4),
+    (5, F5),
+    (6, F6)
+);
+impl_labeled_alt_for_tuple!(
+    (0, F0),
+    (1, F1),
+    (2, F2),
+    (3, F3),
+    (4, F4),
+    (5, F5),
+    (6, F6),
+    (7, F7),
+);
+
+pub trait Location {
+    fn location(&self) -> usize;
+}
+
+impl<T> Location for &T
 where
-    I: ?Sized,
+    T: Location + ?Sized,
 {
-    pub fn into_data(self) -> &'i I {
-        self.data
+    fn location(&self) -> usize {
+        (**self).location()
     }
 }
 
-impl<'i, I> AsBytes for Located<'i, I>
+pub trait ParserExt<I, O, E>: Parser<I, O, E> {
+    fn map_with_location<O2, F>(self, f: F) -> MapWithLocation<Self, F, O>
+    where
+        Self: Sized,
+        F: FnMut(usize, O) -> O2,
+    {
+        MapWithLocation {
+            parser: self,
+            f,
+            phantom: PhantomData,
+        }
+    }
+}
+
+impl<I, O, E, P> ParserExt<I, O, E> for P where P: Parser<I, O, E> {}
+
+pub trait RemapSpans {
+    fn remap<F>(&mut self, f: &F)
+    where
+        F: Fn(Range<usize>) -> Range<usize>;
+}
+
+macro_rules! impl_remap_spans_for_leaf {
+    ($($t:ty),*$(,)?) => {
+        $(
+            impl RemapSpans for $t {
+                fn remap<F>(&mut self, _: &F)
+                where
+                    F: Fn(Range<usize>) -> Range<usize>,
+                {
+                }
+            }
+        )*
+    };
+}
+impl_remap_spans_for_leaf!(
+    (),
+    bool,
+    char,
+    f32,
+    f64,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    String,
+);
+
+impl<T> RemapSpans for Box<T>
 where
-    I: ?Sized,
-    &'i I: AsBytes,
+    T: RemapSpans + ?Sized,
 {
-    fn as_bytes(&self) -> &[u8] {
-        self.data.as_bytes()
+    fn remap<F>(&mut self, f: &F)
+    where
+        F: Fn(Range<usize>) -> Range<usize>,
+    {
+        self.as_mut().remap(f)
     }
 }
 
-impl<'i, I> AsRef<I> for Located<'i, I>
+impl<T> RemapSpans for Option<T>
 where
-    I: ?Sized,
+    T: RemapSpans,
 {
-    fn as_ref(&self) -> &I {
-        self.data
+    fn remap<F>(&mut self, f: &F)
+    where
+        F: Fn(Range<usize>) -> Range<usize>,
+    {
+        if let Some(item) = self.as_mut() {
+            item.remap(f);
+        }
     }
 }
 
-impl<'i, I> Clone for Located<'i, I>
+impl<T> RemapSpans for Vec<T>
 where
-    I: ?Sized,
+    T: RemapSpans,
 {
-    fn clone(&self) -> Self {
-        Located {
-            data: self.data,
-            location: self.location,
+    fn remap<F>(&mut self, f: &F)
+    where
+        F: Fn(Range<usize>) -> Range<usize>,
+    {
+        for item in self.iter_mut() {
+            item.remap(f);
         }
     }
 }
 
-impl<'i, 'u, I, U> Compare<&'u U> for Located<'i, I>
+pub trait SpanError<I>: ParseError<I> {
+    fn from_span(input: I, span: Range<usize>, kind: ErrorKind) -> Self;
+
+    /// Replaces the input and span of an error, keeping its kind.
+    fn respan(self, input: I, span: Range<usize>) -> Self;
+}
+
+impl<I> SpanError<I> for NomError<I> {
+    fn from_span(input: I, _: Range<usize>, kind: ErrorKind) -> Self {
+        NomError::from_error_kind(input, kind)
+    }
+
+    fn respan(self, input: I, _: Range<usize>) -> Self {
+        NomError::new(input, self.code)
+    }
+}
+
+impl<I> SpanError<I> for VerboseError<I> {
+    fn from_span(input: I, _: Range<usize>, kind: ErrorKind) -> Self {
+        VerboseError::from_error_kind(input, kind)
+    }
+
+    // The errors of a `VerboseError` are located by their own inputs, so they
+    // are kept as they are.
+    fn respan(self, _: I, _: Range<usize>) -> Self {
+        self
+    }
+}
+
+/// A receiver of the spans of nodes as they are parsed. See `observe`.
+pub trait SpanObserver {
+    fn on_node(&mut self, kind: &'static str, span: Range<usize>);
+}
+
+/// A set of parsers that may match in any order and gets the span of each
+/// output. See `permutation_spanned`.
+pub trait SpannedPermutation<I, O, E> {
+    fn permute_spanned(&mut self, input: I) -> IResult<I, (O, Vec<Range<usize>>), E>;
+}
+
+macro_rules! impl_spanned_permutation_for_tuple {
+    ($(($n:tt, $f:ident, $o:ident, $x:ident)),+$(,)?) => {
+        impl<I, E, $($f, $o),+> SpannedPermutation<I, ($($o,)+), E> for ($($f,)+)
+        where
+            I: Clone + Location,
+            E: ParseError<I>,
+            $($f: Parser<I, $o, E>,)+
+        {
+            fn permute_spanned(&mut self, mut input: I) -> IResult<I, (($($o,)+), Vec<Range<usize>>), E> {
+                let mut spans = Vec::new();
+                $(let mut $x: Option<$o> = None;)+
+                loop {
+                    if $($x.is_some())&&+ {
+                        return Ok((input, (($($x.unwrap(),)+), spans)));
+                    }
+                    let mut error: Option<E> = None;
+                    let mut matched = false;
+                    $(
+                        if !matched && $x.is_none() {
+                            match self.$n.parse(input.clone()) {
+                                Ok((remaining, output)) => {
+                                    spans.push(input.location()..remaining.location());
+                                    input = remaining;
+                                    $x = Some(output);
+                                    matched = true;
+                                }
+                                Err(ErrorMode::Error(next)) => {
+                                    error = Some(match error {
+                                        Some(error) => error.or(next),
+                                        None => next,
+                                    });
+                                }
+                                Err(mode) => return Err(mode),
+                            }
+                        }
+                    )+
+                    if !matched {
+                        return Err(ErrorMode::Error(E::append(
+                            input,
+                            ErrorKind::Permutation,
+                            error.unwrap(),
+                        )));
+                    }
+                }
+            }
+        }
+    };
+}
+impl_spanned_permutation_for_tuple!((0, F0, O0, x0), (1, F1, O1, x1));
+impl_spanned_permutation_for_tuple!((0, F0, O0, x0), (1, F1, O1, x1), (2, F2, O2, x2));
+impl_spanned_permutation_for_tuple!(
+    (0, F0, O0, x0),
+    (1, F1, O1, x1),
+    (2, F2, O2, x2),
+    (3, F3, O3, x3),
+);
+impl_spanned_permutation_for_tuple!(
+    (0, F0, O0, x0),
+    (1, F1, O1, x1),
+    (2, F2, O2, x2),
+    (3, F3, O3, x3),
+    (4, F4, O4, x4),
+);
+impl_spanned_permutation_for_tuple!(
+    (0, F0, O0, x0),
+    (1, F1, O1, x1),
+    (2, F2, O2, x2),
+    (3, F3, O3, x3),
+    (4, F4, O4, x4),
+    (5, F5, O5, x5),
+);
+impl_spanned_permutation_for_tuple!(
+    (0, F0, O0, x0),
+    (1, F1, O1, x1),
+    (2, F2, O2, x2),
+    (3, F3, O3, x3),
+    (4, F4, O4, x4),
+    (5, F5, O5, x5),
+    (6, F6, O6, x6),
+);
+impl_spanned_permutation_for_tuple!(
+    (0, F0, O0, x0),
+    (1, F1, O1, x1),
+    (2, F2, O2, x2),
+    (3, F3, O3, x3),
+    (4, F4, O4, x4),
+    (5, F5, O5, x5),
+    (6, F6, O6, x6),
+    (7, F7, O7, x7),
+);
+
+/// A sequence of parsers that gets the span of each output. See
+/// `spanned_tuple`.
+pub trait SpannedTuple<I, O, E> {
+    fn parse_spanned(&mut self, input: I) -> IResult<I, (O, Vec<Range<usize>>), E>;
+}
+
+macro_rules! impl_spanned_tuple_for_tuple {
+    ($(($n:tt, $f:ident, $o:ident, $x:ident)),+$(,)?) => {
+        impl<I, E, $($f, $o),+> SpannedTuple<I, ($($o,)+), E> for ($($f,)+)
+        where
+            I: Location,
+            E: ParseError<I>,
+            $($f: Parser<I, $o, E>,)+
+        {
+            fn parse_spanned(&mut self, input: I) -> IResult<I, (($($o,)+), Vec<Range<usize>>), E> {
+                let mut spans = Vec::new();
+                $(
+                    let start = input.location();
+                    let (input, $x) = self.$n.parse(input)?;
+                    spans.push(start..input.location());
+                )+
+                Ok((input, (($($x,)+), spans)))
+            }
+        }
+    };
+}
+impl_spanned_tuple_for_tuple!((0, F0, O0, x0));
+impl_spanned_tuple_for_tuple!((0, F0, O0, x0), (1, F1, O1, x1));
+impl_spanned_tuple_for_tuple!((0, F0, O0, x0), (1, F1, O1, x1), (2, F2, O2, x2));
+impl_spanned_tuple_for_tuple!(
+    (0, F0, O0, x0),
+    (1, F1, O1, x1),
+    (2, F2, O2, x2),
+    (3, F3, O3, x3),
+);
+impl_spanned_tuple_for_tuple!(
+    (0, F0, O0, x0),
+    (1, F1, O1, x1),
+    (2, F2, O2, x2),
+    (3, F3, O3, x3),
+    (4, F4, O4, x4),
+);
+impl_spanned_tuple_for_tuple!(
+    (0, F0, O0, x0),
+    (1, F1, O1, x1),
+    (2, F2, O2, x2),
+    (3, F3, O3, x3),
+    (4, F4, O4, x4),
+    (5, F5, O5, x5),
+);
+impl_spanned_tuple_for_tuple!(
+    (0, F0, O0, x0),
+    (1, F1, O1, x1),
+    (2, F2, O2, x2),
+    (3, F3, O3, x3),
+    (4, F4, O4, x4),
+    (5, F5, O5, x5),
+    (6, F6, O6, x6),
+);
+impl_spanned_tuple_for_tuple!(
+    (0, F0, O0, x0),
+    (1, F1, O1, x1),
+    (2, F2, O2, x2),
+    (3, F3, O3, x3),
+    (4, F4, O4, x4),
+    (5, F5, O5, x5),
+    (6, F6, O6, x6),
+    (7, F7, O7, x7),
+);
+
+/// A choice of parsers that gets the index of the parser that matched. See
+/// `tagged_alt`.
+pub trait TaggedAlt<I, O, E> {
+    fn choice(&mut self, input: I) -> IResult<I, (usize, O), E>;
+}
+
+macro_rules! impl_tagged_alt_for_tuple {
+    ($(($n:tt, $f:ident)),+$(,)?) => {
+        impl<I, O, E, $($f),+> TaggedAlt<I, O, E> for ($($f,)+)
+        where
+            I: Clone,
+            E: ParseError<I>,
+            $($f: Parser<I, O, E>,)+
+        {
+            fn choice(&mut self, input: I) -> IResult<I, (usize, O), E> {
+                let mut error: Option<E> = None;
+                $(
+                    match self.$n.parse(input.clone()) {
+                        Ok((remaining, output)) => return Ok((remaining, ($n, output))),
+                        Err(ErrorMode::Error(next)) => {
+                            error = Some(match error {
+                                Some(error) => error.or(next),
+                                None => next,
+                            });
+                        }
+                        Err(mode) => return Err(mode),
+                    }
+                )+
+                Err(ErrorMode::Error(E::append(input, ErrorKind::Alt, error.unwrap())))
+            }
+        }
+    };
+}
+impl_tagged_alt_for_tuple!((0, F0), (1, F1));
+impl_tagged_alt_for_tuple!((0, F0), (1, F1), (2, F2));
+impl_tagged_alt_for_tuple!((0, F0), (1, F1), (2, F2), (3, F3));
+impl_tagged_alt_for_tuple!((0, F0), (1, F1), (2, F2), (3, F3), (4, F4));
+impl_tagged_alt_for_tuple!((0, F0), (1, F1), (2, F2), (3, F3), (4, F4), (5, F5));
+impl_tagged_alt_for_tuple!(
+    (0, F0),
+    (1, F1),
+    (2, F2),
+    (3, F3),
+    (4, F4),
+    (5, F5),
+    (6, F6)
+);
+impl_tagged_alt_for_tuple!(
+    (0, F0),
+    (1, F1),
+    (2, F2),
+    (3, F3),
+    (4, F4),
+    (5, F5),
+    (6, F6),
+    (7, F7),
+);
+
+/// Bit-level input with locations measured in bits.
+///
+/// This wraps the `(I, usize)` input of parsers in `nom::bits`, where the
+/// `usize` is an offset in bits into the first byte of `I`. When `I` is
+/// located, such as `Located<'_, [u8]>`, the location of a `BitLocated` is the
+/// location of `I` in bits, so combinators like `span` report bit ranges.
+///
+/// Use `bits_located` to apply a bit-level parser to byte-level input and
+/// `bit_located` to apply parsers from `nom::bits`. Byte-level parsers applied
+/// via `nom::bits::bytes` within `bit_located` continue to report byte
+/// locations.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct BitLocated<I> {
+    data: I,
+    offset: usize,
+}
+
+impl<I> BitLocated<I> {
+    pub fn new(data: I, offset: usize) -> Self {
+        BitLocated { data, offset }
+    }
+
+    pub fn into_inner(self) -> (I, usize) {
+        (self.data, self.offset)
+    }
+}
+
+impl<I> From<(I, usize)> for BitLocated<I> {
+    fn from((data, offset): (I, usize)) -> Self {
+        BitLocated::new(data, offset)
+    }
+}
+
+impl<I> Location for BitLocated<I>
 where
-    I: ?Sized,
-    U: ?Sized,
-    &'i I: Compare<&'u U>,
-    &'u U: Into<Located<'u, U>>,
+    I: Location,
 {
-    fn compare(&self, other: &'u U) -> CompareResult {
-        self.data.compare(other.into().data)
+    fn location(&self) -> usize {
+        (self.data.location() * 8) + self.offset
     }
+}
 
-    fn compare_no_case(&self, other: &'u U) -> CompareResult {
-        self.data.compare_no_case(other.into().data)
+/// A mapping from locations in generated code to the spans in a source from
+/// which the code was generated.
+///
+/// Unlike `SpanMap`, this only maps from generated code to the source, such
+/// as to attribute a diagnostic reported by a downstream compiler to the
+/// source construct that produced the code.
+#[cfg(feature = "codegen")]
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct BlameMap {
+    entries: Vec<(Range<usize>, Range<usize>)>,
+}
+
+#[cfg(feature = "codegen")]
+impl BlameMap {
+    pub fn new() -> Self {
+        BlameMap::default()
+    }
+
+    /// Associates a span in the generated code with a span in the source.
+    pub fn insert(&mut self, generated: Range<usize>, source: Range<usize>) {
+        self.entries.push((generated, source));
+    }
+
+    /// Gets the span in the source of the smallest span in the generated code
+    /// that contains the given location.
+    pub fn blame(&self, location: usize) -> Option<Range<usize>> {
+        self.entries
+            .iter()
+            .filter(|(generated, _)| generated.contains(&location))
+            .min_by_key(|(generated, _)| generated.len())
+            .map(|(_, source)| source.clone())
     }
 }
 
-impl<'i, I> Copy for Located<'i, I> where I: ?Sized {}
+/// Compares and hashes a wrapped value by its fragment, ignoring location.
+#[derive(Clone, Copy, Debug)]
+pub struct ByFragment<T>(pub T);
 
-impl<'i, I> Deref for Located<'i, I>
+impl<T> ByFragment<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+// `Located` does not implement `Borrow`, because its comparisons and hashing
+// consider location and so are inconsistent with those of its fragment.
+impl<'i, I> Borrow<I> for ByFragment<Located<'i, I>>
 where
     I: ?Sized,
 {
-    type Target = I;
-
-    fn deref(&self) -> &Self::Target {
-        self.as_ref()
+    fn borrow(&self) -> &I {
+        self.0.data
     }
 }
 
-impl<'i, I> Display for Located<'i, I>
+impl<'i, I> Eq for ByFragment<Located<'i, I>> where I: Eq + ?Sized {}
+
+impl<'i, I> Hash for ByFragment<Located<'i, I>>
 where
-    I: Display + ?Sized,
+    I: Hash + ?Sized,
 {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        Display::fmt(self.data, f)
+    fn hash<H>(&self, state: &mut H)
+    where
+        H: Hasher,
+    {
+        self.0.data.hash(state)
     }
 }
 
-impl<'i, I> ExtendInto for Located<'i, I>
+impl<'i, I> PartialEq for ByFragment<Located<'i, I>>
 where
-    I: ?Sized,
-    &'i I: ExtendInto,
+    I: PartialEq + ?Sized,
 {
-    type Item = <&'i I as ExtendInto>::Item;
-    type Extender = <&'i I as ExtendInto>::Extender;
+    fn eq(&self, other: &Self) -> bool {
+        self.0.data == other.0.data
+    }
+}
 
-    fn new_builder(&self) -> Self::Extender {
-        self.data.new_builder()
+/// A fragment of UTF-8 encoded bytes and its byte and `char` locations.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct CharLocated<'i> {
+    data: &'i [u8],
+    location: usize,
+    char_location: usize,
+}
+
+impl<'i> CharLocated<'i> {
+    pub fn into_data(self) -> &'i [u8] {
+        self.data
     }
 
-    fn extend_into(&self, extender: &mut Self::Extender) {
-        self.data.extend_into(extender)
+    pub fn char_location(&self) -> usize {
+        self.char_location
     }
 }
 
-impl<'i, I> From<Located<'i, I>> for Cow<'i, I>
-where
-    I: ?Sized + ToOwned,
-{
-    fn from(fragment: Located<'i, I>) -> Self {
-        Cow::Borrowed(fragment.data)
+impl<'i> AsBytes for CharLocated<'i> {
+    fn as_bytes(&self) -> &[u8] {
+        self.data
     }
 }
 
-impl<'i, I> From<&'i I> for Located<'i, I>
-where
-    I: ?Sized,
-{
-    fn from(data: &'i I) -> Self {
-        Located { data, location: 0 }
+impl<'i> AsRef<[u8]> for CharLocated<'i> {
+    fn as_ref(&self) -> &[u8] {
+        self.data
     }
 }
 
-impl<'i, I> InputIter for Located<'i, I>
+impl<'i, U> Compare<U> for CharLocated<'i>
 where
-    I: ?Sized,
-    &'i I: InputIter,
+    &'i [u8]: Compare<U>,
 {
-    type Item = <&'i I as InputIter>::Item;
-    type Iter = <&'i I as InputIter>::Iter;
-    type IterElem = <&'i I as InputIter>::IterElem;
+    fn compare(&self, other: U) -> CompareResult {
+        self.data.compare(other)
+    }
+
+    fn compare_no_case(&self, other: U) -> CompareResult {
+        self.data.compare_no_case(other)
+    }
+}
+
+impl<'i> Deref for CharLocated<'i> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        self.data
+    }
+}
+
+impl<'i> From<&'i [u8]> for CharLocated<'i> {
+    fn from(data: &'i [u8]) -> Self {
+        CharLocated {
+            data,
+            location: 0,
+            char_location: 0,
+        }
+    }
+}
+
+impl<'i> InputIter for CharLocated<'i> {
+    type Item = <&'i [u8] as InputIter>::Item;
+    type Iter = <&'i [u8] as InputIter>::Iter;
+    type IterElem = <&'i [u8] as InputIter>::IterElem;
 
     fn iter_indices(&self) -> Self::Iter {
         self.data.iter_indices()
@@ -164,21 +719,13 @@ where
     }
 }
 
-impl<'i, I> InputLength for Located<'i, I>
-where
-    I: ?Sized,
-    &'i I: InputLength,
-{
+impl<'i> InputLength for CharLocated<'i> {
     fn input_len(&self) -> usize {
-        self.data.input_len()
+        self.data.len()
     }
 }
 
-impl<'i, I> InputTake for Located<'i, I>
-where
-    I: ?Sized,
-    Self: Slice<RangeFrom<usize>> + Slice<RangeTo<usize>>,
-{
+impl<'i> InputTake for CharLocated<'i> {
     fn take(&self, count: usize) -> Self {
         self.slice(..count)
     }
@@ -188,13 +735,8 @@ where
     }
 }
 
-impl<'i, I> InputTakeAtPosition for Located<'i, I>
-where
-    I: ?Sized,
-    &'i I: InputIter + InputLength + InputTakeAtPosition,
-    Self: Slice<RangeFrom<usize>> + Slice<RangeTo<usize>>,
-{
-    type Item = <&'i I as InputIter>::Item;
+impl<'i> InputTakeAtPosition for CharLocated<'i> {
+    type Item = u8;
 
     fn split_at_position_complete<P, E>(&self, predicate: P) -> IResult<Self, Self, E>
     where
@@ -243,7 +785,7 @@ where
             Some(0) => Err(ErrorMode::Error(E::from_error_kind(*self, kind))),
             Some(n) => Ok(self.take_split(n)),
             None => {
-                if self.data.input_len() == 0 {
+                if self.data.is_empty() {
                     Err(ErrorMode::Error(E::from_error_kind(*self, kind)))
                 }
                 else {
@@ -254,319 +796,8536 @@ where
     }
 }
 
-impl<'i, I> Location for Located<'i, I>
-where
-    I: ?Sized,
-{
+impl<'i> Location for CharLocated<'i> {
     fn location(&self) -> usize {
         self.location
     }
 }
 
-impl<'i, I> Offset for Located<'i, I>
-where
-    I: ?Sized,
-{
+impl<'i> Offset for CharLocated<'i> {
     fn offset(&self, other: &Self) -> usize {
         other.location.saturating_sub(self.location)
     }
 }
 
-impl<'i, I, R> Slice<R> for Located<'i, I>
+impl<'i, R> Slice<R> for CharLocated<'i>
 where
-    I: ?Sized,
-    &'i I: AsBytes + Offset + Slice<R> + Slice<RangeTo<usize>>,
+    &'i [u8]: Slice<R>,
 {
     fn slice(&self, range: R) -> Self {
         let sliced = self.data.slice(range);
-        let offset = self.data.offset(&sliced);
-        Located {
+        let offset = self.data.offset(sliced);
+        let chars = self.data[..offset]
+            .iter()
+            .filter(|&&byte| (byte & 0xC0) != 0x80)
+            .count();
+        CharLocated {
             data: sliced,
             location: self.location + offset,
+            char_location: self.char_location + chars,
         }
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
-pub struct Stateful<I, T> {
-    data: I,
-    pub state: T,
+/// The comment syntax of a language. See `skip_trivia`.
+///
+/// Line comments begin with `line` and end before the next newline. Block
+/// comments are delimited by the pair in `block` and may contain other block
+/// comments when `nested` is `true`.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct CommentStyle {
+    pub line: Option<&'static str>,
+    pub block: Option<(&'static str, &'static str)>,
+    pub nested: bool,
 }
 
-impl<I, T> Stateful<I, T> {
-    pub fn new(data: I, state: T) -> Self {
-        Stateful { data, state }
-    }
+/// State with shared immutable configuration and mutable state.
+///
+/// The configuration is reference counted, so it is not deeply cloned when
+/// input is split.
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub struct Context<C, S> {
+    config: Rc<C>,
+    pub state: S,
+}
 
-    fn clone_map<F>(&self, mut f: F) -> Self
-    where
-        T: Clone,
-        F: FnMut(&I) -> I,
-    {
-        Stateful {
-            data: f(&self.data),
-            state: self.state.clone(),
+impl<C, S> Context<C, S> {
+    pub fn new(config: impl Into<Rc<C>>, state: S) -> Self {
+        Context {
+            config: config.into(),
+            state,
         }
     }
 
-    fn clone_map_result<E, F>(&self, f: F) -> IResult<Self, Self, E>
-    where
-        E: ParseError<Self>,
-        T: Clone,
-        F: FnOnce(&I) -> IResult<I, I>,
-    {
-        let map_error = |error: NomError<I>| {
-            E::from_error_kind(
-                Stateful {
-                    data: error.input,
-                    state: self.state.clone(),
-                },
-                error.code,
-            )
-        };
-        f(&self.data)
-            .map(|(remaining, output)| {
-                (
-                    Stateful {
-                        data: remaining,
-                        state: self.state.clone(),
-                    },
-                    Stateful {
-                        data: output,
-                        state: self.state.clone(),
-                    },
-                )
-            })
-            .map_err(|error| match error {
-                ErrorMode::Error(error) => ErrorMode::Error(map_error(error)),
-                ErrorMode::Failure(error) => ErrorMode::Failure(map_error(error)),
-                ErrorMode::Incomplete(needed) => ErrorMode::Incomplete(needed),
-            })
+    pub fn config(&self) -> &C {
+        self.config.as_ref()
+    }
+
+    pub fn shared_config(&self) -> Rc<C> {
+        self.config.clone()
     }
 }
 
-impl<I, T> AsBytes for Stateful<I, T>
+impl<C, S> Clone for Context<C, S>
 where
-    I: AsBytes,
+    S: Clone,
 {
-    fn as_bytes(&self) -> &[u8] {
-        self.data.as_bytes()
+    fn clone(&self) -> Self {
+        Context {
+            config: self.config.clone(),
+            state: self.state.clone(),
+        }
     }
 }
 
-impl<I, T> AsRef<I> for Stateful<I, T> {
-    fn as_ref(&self) -> &I {
-        &self.data
-    }
+pub type Contextual<I, C, S> = Stateful<I, Context<C, S>>;
+
+/// A gap or overlap between spans or a span beyond the source, as found by
+/// `validate_contiguity`.
+///
+/// The index is that of the span that follows the gap, overlaps the preceding
+/// span, or extends beyond the source, or the number of spans for a gap at the
+/// end of the source.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum ContiguityError {
+    Gap { index: usize, span: Range<usize> },
+    OutOfBounds { index: usize, span: Range<usize> },
+    Overlap { index: usize, span: Range<usize> },
 }
 
-impl<I, T, U> Compare<U> for Stateful<I, T>
-where
-    I: Compare<U>,
-{
-    fn compare(&self, other: U) -> CompareResult {
-        self.data.compare(other)
+impl Display for ContiguityError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ContiguityError::Gap { index, span } => {
+                write!(
+                    f,
+                    "gap at {}..{} before span {}",
+                    span.start, span.end, index
+                )
+            }
+            ContiguityError::OutOfBounds { index, span } => {
+                write!(
+                    f,
+                    "out of bounds at {}..{} in span {}",
+                    span.start, span.end, index
+                )
+            }
+            ContiguityError::Overlap { index, span } => {
+                write!(
+                    f,
+                    "overlap at {}..{} in span {}",
+                    span.start, span.end, index
+                )
+            }
+        }
     }
+}
 
-    fn compare_no_case(&self, other: U) -> CompareResult {
-        self.data.compare_no_case(other)
+/// The spans of a key that occurs more than once, as found by `kv_map`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DuplicateEntry {
+    pub original: Range<usize>,
+    pub duplicate: Range<usize>,
+}
+
+/// A key that occurs more than once, as found by `unique_key`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct DuplicateKey {
+    pub key: String,
+    pub original: usize,
+    pub duplicate: usize,
+}
+
+impl Display for DuplicateKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "duplicate key `{}` at {}, originally at {}",
+            self.key, self.duplicate, self.original,
+        )
     }
 }
 
-impl<I, T> Deref for Stateful<I, T> {
-    type Target = I;
+/// The labels of alternatives that failed at a location, as found by
+/// `alt_expected`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Expected {
+    pub location: usize,
+    pub labels: Vec<&'static str>,
+}
 
-    fn deref(&self) -> &Self::Target {
-        self.as_ref()
+impl Display for Expected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "expected one of {} at {}",
+            self.labels.join(", "),
+            self.location,
+        )
     }
 }
 
-impl<I, T> Display for Stateful<I, T>
+/// An error that is the furthest of the errors of alternatives.
+///
+/// When combined via `or`, such as by `alt` and `furthest_alt`, the error
+/// with the furthest location is kept, so the error of a branch that makes
+/// the most progress is reported even if a later branch fails sooner. When
+/// errors are equally far, their contexts are merged.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct FurthestError(pub LocatedError);
+
+impl<I> ContextError<I> for FurthestError
 where
-    I: Display,
+    I: Location,
 {
+    fn add_context(input: I, label: &'static str, other: Self) -> Self {
+        FurthestError(LocatedError::add_context(input, label, other.0))
+    }
+}
+
+impl Display for FurthestError {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        Display::fmt(&self.data, f)
+        self.0.fmt(f)
     }
 }
 
-impl<I, T> ExtendInto for Stateful<I, T>
+impl From<FurthestError> for LocatedError {
+    fn from(error: FurthestError) -> Self {
+        error.0
+    }
+}
+
+impl<I> ParseError<I> for FurthestError
 where
-    I: ExtendInto,
+    I: Location,
 {
-    type Item = I::Item;
-    type Extender = I::Extender;
+    fn from_error_kind(input: I, kind: ErrorKind) -> Self {
+        FurthestError(LocatedError::from_error_kind(input, kind))
+    }
 
-    fn new_builder(&self) -> Self::Extender {
-        self.data.new_builder()
+    fn append(_: I, _: ErrorKind, other: Self) -> Self {
+        other
     }
 
-    fn extend_into(&self, extender: &mut Self::Extender) {
-        self.data.extend_into(extender)
+    fn or(mut self, other: Self) -> Self {
+        if other.0.location() > self.0.location() {
+            other
+        }
+        else {
+            if other.0.location() == self.0.location() {
+                for entry in other.0.context {
+                    if !self.0.context.contains(&entry) {
+                        self.0.context.push(entry);
+                    }
+                }
+            }
+            self
+        }
     }
 }
 
-impl<I, T> InputIter for Stateful<I, T>
+impl<I> SpanError<I> for FurthestError
 where
-    I: InputIter,
+    I: Location,
 {
-    type Item = I::Item;
-    type Iter = I::Iter;
-    type IterElem = I::IterElem;
+    fn from_span(input: I, span: Range<usize>, kind: ErrorKind) -> Self {
+        FurthestError(LocatedError::from_span(input, span, kind))
+    }
 
-    fn iter_indices(&self) -> Self::Iter {
-        self.data.iter_indices()
+    fn respan(self, input: I, span: Range<usize>) -> Self {
+        FurthestError(self.0.respan(input, span))
     }
+}
 
-    fn iter_elements(&self) -> Self::IterElem {
-        self.data.iter_elements()
+/// An index into a `SpanArena`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Handle(usize);
+
+impl Handle {
+    pub fn index(&self) -> usize {
+        self.0
     }
+}
 
-    fn position<P>(&self, predicate: P) -> Option<usize>
+/// An incomplete frame in streaming input and the location at which it
+/// begins. See `incomplete_frame`.
+///
+/// Parsing can be resumed from `start` once more input is available.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IncompleteFrame {
+    pub start: usize,
+    pub needed: Needed,
+}
+
+impl Display for IncompleteFrame {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.needed {
+            Needed::Size(size) => write!(
+                f,
+                "incomplete frame at {}: {} more items needed",
+                self.start, size,
+            ),
+            Needed::Unknown => write!(f, "incomplete frame at {}", self.start),
+        }
+    }
+}
+
+/// Key-value entries and duplicate keys, typically as the state of `Stateful`
+/// input. See `kv_map`.
+///
+/// The first occurrence of a key is kept and any subsequent occurrences are
+/// recorded as duplicates.
+#[derive(Clone, Debug)]
+pub struct KeyValues<K, V> {
+    pub entries: HashMap<K, Spanned<V>>,
+    pub duplicates: Vec<DuplicateEntry>,
+    keys: HashMap<K, Range<usize>>,
+}
+
+impl<K, V> KeyValues<K, V> {
+    pub fn new() -> Self {
+        KeyValues {
+            entries: HashMap::new(),
+            duplicates: Vec::new(),
+            keys: HashMap::new(),
+        }
+    }
+
+    /// Gets the span of the first occurrence of a key.
+    pub fn key_span(&self, key: &K) -> Option<Range<usize>>
     where
-        P: Fn(Self::Item) -> bool,
+        K: Eq + Hash,
     {
-        self.data.position(predicate)
+        self.keys.get(key).cloned()
     }
+}
 
-    fn slice_index(&self, count: usize) -> Result<usize, Needed> {
-        self.data.slice_index(count)
+impl<K, V> Default for KeyValues<K, V> {
+    fn default() -> Self {
+        KeyValues::new()
     }
 }
 
-impl<I, T> InputLength for Stateful<I, T>
-where
-    I: InputLength,
-{
-    fn input_len(&self) -> usize {
-        self.data.input_len()
-    }
+/// A rule that is re-entered at the same location without consuming input.
+/// See `recursion_guard`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct LeftRecursion {
+    pub rule: &'static str,
+    pub location: usize,
 }
 
-impl<I, T> InputTake for Stateful<I, T>
-where
-    I: InputTake,
-    T: Clone,
-{
-    fn take(&self, count: usize) -> Self {
-        self.clone_map(move |data| data.take(count))
+impl Display for LeftRecursion {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "left recursion in {} at {}", self.rule, self.location,)
     }
+}
 
-    fn take_split(&self, count: usize) -> (Self, Self) {
-        let (left, right) = self.data.take_split(count);
-        (
-            Stateful {
-                data: left,
-                state: self.state.clone(),
-            },
-            Stateful {
-                data: right,
-                state: self.state.clone(),
-            },
-        )
-    }
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct LineColumn {
+    pub line: usize,
+    pub column: usize,
 }
 
-impl<I, T> InputTakeAtPosition for Stateful<I, T>
-where
-    I: InputTakeAtPosition,
-    T: Clone,
-{
-    type Item = <I as InputTakeAtPosition>::Item;
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    CrLf,
+    Cr,
+}
 
-    fn split_at_position_complete<P, E>(&self, predicate: P) -> IResult<Self, Self, E>
-    where
-        P: Fn(Self::Item) -> bool,
-        E: ParseError<Self>,
-    {
-        self.clone_map_result(move |data| data.split_at_position_complete(predicate))
+/// An index of the lines in a source that maps locations to lines and
+/// columns.
+///
+/// Lines and columns are zero-based and columns are measured in bytes.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl LineIndex {
+    pub fn new(source: &(impl AsRef<[u8]> + ?Sized)) -> Self {
+        let source = source.as_ref();
+        let starts = iter::once(0)
+            .chain(
+                source
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, &byte)| byte == b'\n')
+                    .map(|(n, _)| n + 1),
+            )
+            .collect();
+        LineIndex {
+            starts,
+            len: source.len(),
+        }
     }
 
-    fn split_at_position<P, E>(&self, predicate: P) -> IResult<Self, Self, E>
-    where
-        P: Fn(Self::Item) -> bool,
-        E: ParseError<Self>,
-    {
-        self.clone_map_result(move |data| data.split_at_position(predicate))
+    pub fn line_col(&self, location: usize) -> LineColumn {
+        let location = location.min(self.len);
+        let line = match self.starts.binary_search(&location) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        LineColumn {
+            line,
+            column: location - self.starts[line],
+        }
     }
 
-    fn split_at_position1<P, E>(&self, predicate: P, kind: ErrorKind) -> IResult<Self, Self, E>
-    where
-        P: Fn(Self::Item) -> bool,
-        E: ParseError<Self>,
-    {
-        self.clone_map_result(move |data| data.split_at_position1(predicate, kind))
+    /// Gets the span of a line, including its line terminator.
+    pub fn line_span(&self, line: usize) -> Option<Range<usize>> {
+        let start = *self.starts.get(line)?;
+        let end = self.starts.get(line + 1).copied().unwrap_or(self.len);
+        Some(start..end)
     }
+}
 
-    fn split_at_position1_complete<P, E>(
-        &self,
-        predicate: P,
-        kind: ErrorKind,
-    ) -> IResult<Self, Self, E>
-    where
-        P: Fn(Self::Item) -> bool,
-        E: ParseError<Self>,
-    {
-        self.clone_map_result(move |data| data.split_at_position1_complete(predicate, kind))
+/// An owned error with the span in which it occurred.
+///
+/// Context is recorded as pairs of locations and labels, from the innermost
+/// to the outermost context.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct LocatedError {
+    pub span: Range<usize>,
+    pub kind: ErrorKind,
+    pub context: Vec<(usize, &'static str)>,
+}
+
+impl LocatedError {
+    pub fn new(span: Range<usize>, kind: ErrorKind) -> Self {
+        LocatedError {
+            span,
+            kind,
+            context: Vec::new(),
+        }
+    }
+
+    pub fn location(&self) -> usize {
+        self.span.start
     }
 }
 
-impl<I, T> Location for Stateful<I, T>
+impl<I> ContextError<I> for LocatedError
 where
     I: Location,
 {
-    fn location(&self) -> usize {
-        self.data.location()
+    fn add_context(input: I, label: &'static str, mut other: Self) -> Self {
+        other.context.push((input.location(), label));
+        other
     }
 }
 
-impl<I, T> Offset for Stateful<I, T>
-where
-    I: Offset,
-{
-    fn offset(&self, other: &Self) -> usize {
-        self.data.offset(&other.data)
+impl Display for LocatedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}..{}",
+            self.kind.description(),
+            self.span.start,
+            self.span.end,
+        )?;
+        for (location, label) in self.context.iter() {
+            write!(f, ", in {} at {}", label, location)?;
+        }
+        Ok(())
     }
 }
 
-impl<I, T, R> Slice<R> for Stateful<I, T>
+impl<I> ParseError<I> for LocatedError
 where
-    I: Slice<R>,
-    T: Clone,
+    I: Location,
 {
-    fn slice(&self, range: R) -> Self {
-        let data = self.data.slice(range);
-        Stateful {
-            data,
-            state: self.state.clone(),
-        }
+    fn from_error_kind(input: I, kind: ErrorKind) -> Self {
+        let location = input.location();
+        LocatedError::new(location..location, kind)
+    }
+
+    fn append(_: I, _: ErrorKind, other: Self) -> Self {
+        other
     }
 }
 
-pub fn bof<I, E>(input: I) -> IResult<I, I, E>
+impl<I> SpanError<I> for LocatedError
 where
-    I: Clone + Location,
-    E: ParseError<I>,
+    I: Location,
 {
-    if input.location() == 0 {
-        Ok((input.clone(), input))
+    fn from_span(_: I, span: Range<usize>, kind: ErrorKind) -> Self {
+        LocatedError::new(span, kind)
     }
-    else {
-        Err(ErrorMode::Error(E::from_error_kind(input, ErrorKind::Eof)))
+
+    fn respan(self, _: I, span: Range<usize>) -> Self {
+        LocatedError { span, ..self }
     }
 }
 
-pub fn span<I, O, E, F>(mut parser: F) -> impl FnMut(I) -> IResult<I, ((usize, usize), O), E>
+/// A fragment of input and its location.
+///
+/// Comparisons and hashing consider both the fragment and its location, so
+/// the same fragment at different locations is distinct. This distinguishes
+/// inputs at different positions in a parse, such as the remaining input of
+/// parsers. Use [`Located::fragment_eq`] or [`ByFragment`] to compare and hash
+/// by fragment only.
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub struct Located<'i, I>
 where
-    I: Clone + Location,
-    E: ParseError<I>,
-    F: Parser<I, O, E>,
+    I: ?Sized,
 {
-    move |input: I| {
-        let start = input.location();
-        parser.parse(input).map(move |(remaining, output)| {
-            let end = remaining.location();
-            (remaining, ((start, end.saturating_sub(start)), output))
-        })
+    data: &'i I,
+    location: usize,
+}
+
+impl<'i, I> Located<'i, I>
+where
+    I: ?Sized,
+{
+    pub fn new(data: &'i I, location: usize) -> Self {
+        Located { data, location }
+    }
+
+    pub fn into_data(self) -> &'i I {
+        self.data
+    }
+
+    pub fn as_located_ref(&self) -> Located<'i, I::Target>
+    where
+        I: Deref,
+    {
+        Located {
+            data: self.data.deref(),
+            location: self.location,
+        }
+    }
+
+    /// Gets a located sub-fragment or `None` if `fragment` is not within the
+    /// fragment of this input.
+    pub fn try_slice_to(&self, fragment: &'i I) -> Option<Self>
+    where
+        &'i I: AsBytes,
+    {
+        let outer = self.data.as_bytes();
+        let inner = fragment.as_bytes();
+        let start = (inner.as_ptr() as usize).checked_sub(outer.as_ptr() as usize)?;
+        if start + inner.len() <= outer.len() {
+            Some(Located {
+                data: fragment,
+                location: self.location + start,
+            })
+        }
+        else {
+            None
+        }
+    }
+
+    pub fn fragment_eq<U>(&self, other: &Located<'_, U>) -> bool
+    where
+        I: PartialEq<U>,
+        U: ?Sized,
+    {
+        self.data == other.data
+    }
+}
+
+impl<'i, I> AsBytes for Located<'i, I>
+where
+    I: ?Sized,
+    &'i I: AsBytes,
+{
+    fn as_bytes(&self) -> &[u8] {
+        self.data.as_bytes()
+    }
+}
+
+impl<'i> AsRef<[u8]> for Located<'i, str> {
+    fn as_ref(&self) -> &[u8] {
+        self.data.as_bytes()
+    }
+}
+
+impl<'i> AsRef<[u8]> for Located<'i, Vec<u8>> {
+    fn as_ref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl<'i, I> AsRef<I> for Located<'i, I>
+where
+    I: ?Sized,
+{
+    fn as_ref(&self) -> &I {
+        self.data
+    }
+}
+
+impl<'i> AsRef<str> for Located<'i, String> {
+    fn as_ref(&self) -> &str {
+        self.data
+    }
+}
+
+#[allow(clippy::non_canonical_clone_impl)]
+impl<'i, I> Clone for Located<'i, I>
+where
+    I: ?Sized,
+{
+    fn clone(&self) -> Self {
+        Located {
+            data: self.data,
+            location: self.location,
+        }
+    }
+}
+
+impl<'i, 'u, I, U> Compare<&'u U> for Located<'i, I>
+where
+    I: ?Sized,
+    U: ?Sized,
+    &'i I: Compare<&'u U>,
+    &'u U: Into<Located<'u, U>>,
+{
+    fn compare(&self, other: &'u U) -> CompareResult {
+        self.data.compare(other.into().data)
+    }
+
+    fn compare_no_case(&self, other: &'u U) -> CompareResult {
+        self.data.compare_no_case(other.into().data)
+    }
+}
+
+impl<'i, 'u, I, U> Compare<Located<'u, U>> for Located<'i, I>
+where
+    I: ?Sized,
+    U: ?Sized,
+    &'i I: Compare<&'u U>,
+{
+    fn compare(&self, other: Located<'u, U>) -> CompareResult {
+        self.data.compare(other.data)
+    }
+
+    fn compare_no_case(&self, other: Located<'u, U>) -> CompareResult {
+        self.data.compare_no_case(other.data)
+    }
+}
+
+impl<'i, I> Copy for Located<'i, I> where I: ?Sized {}
+
+impl<'i, I> Deref for Located<'i, I>
+where
+    I: ?Sized,
+{
+    type Target = I;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_ref()
+    }
+}
+
+impl<'i, I> Display for Located<'i, I>
+where
+    I: Display + ?Sized,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(self.data, f)
+    }
+}
+
+impl<'i, I> ExtendInto for Located<'i, I>
+where
+    I: ?Sized,
+    &'i I: ExtendInto,
+{
+    type Item = <&'i I as ExtendInto>::Item;
+    type Extender = <&'i I as ExtendInto>::Extender;
+
+    fn new_builder(&self) -> Self::Extender {
+        self.data.new_builder()
+    }
+
+    fn extend_into(&self, extender: &mut Self::Extender) {
+        self.data.extend_into(extender)
+    }
+}
+
+impl<'i, I> From<Located<'i, I>> for Cow<'i, I>
+where
+    I: ?Sized + ToOwned,
+{
+    fn from(fragment: Located<'i, I>) -> Self {
+        Cow::Borrowed(fragment.data)
+    }
+}
+
+impl<'i, I> From<Located<'i, I>> for Range<usize>
+where
+    I: ?Sized,
+    Located<'i, I>: InputLength,
+{
+    fn from(fragment: Located<'i, I>) -> Self {
+        fragment.location..(fragment.location + fragment.input_len())
+    }
+}
+
+impl<'i, I> From<Located<'i, I>> for (&'i I, usize)
+where
+    I: ?Sized,
+{
+    fn from(fragment: Located<'i, I>) -> Self {
+        (fragment.data, fragment.location)
+    }
+}
+
+impl<'i, I> From<&'i I> for Located<'i, I>
+where
+    I: ?Sized,
+{
+    fn from(data: &'i I) -> Self {
+        Located { data, location: 0 }
+    }
+}
+
+/// Locates the bytes of an `OsStr`.
+///
+/// This is only available on Unix, where `OsStr` has a stable byte
+/// representation. On Windows, `OsStr` is not exposed as bytes and must
+/// instead be converted, such as via `OsStr::to_str`, in which case locations
+/// refer to the converted text.
+#[cfg(unix)]
+impl<'i> From<&'i OsStr> for Located<'i, [u8]> {
+    fn from(data: &'i OsStr) -> Self {
+        data.as_bytes().into()
+    }
+}
+
+/// Locates the bytes of a `Path`.
+///
+/// See the `From<&OsStr>` implementation for platform limitations.
+#[cfg(unix)]
+impl<'i> From<&'i Path> for Located<'i, [u8]> {
+    fn from(data: &'i Path) -> Self {
+        data.as_os_str().into()
+    }
+}
+
+impl<'i, I> From<(&'i I, usize)> for Located<'i, I>
+where
+    I: ?Sized,
+{
+    fn from((data, location): (&'i I, usize)) -> Self {
+        Located { data, location }
+    }
+}
+
+impl<'i, I> InputIter for Located<'i, I>
+where
+    I: ?Sized,
+    &'i I: InputIter,
+{
+    type Item = <&'i I as InputIter>::Item;
+    type Iter = <&'i I as InputIter>::Iter;
+    type IterElem = <&'i I as InputIter>::IterElem;
+
+    fn iter_indices(&self) -> Self::Iter {
+        self.data.iter_indices()
+    }
+
+    fn iter_elements(&self) -> Self::IterElem {
+        self.data.iter_elements()
+    }
+
+    fn position<P>(&self, predicate: P) -> Option<usize>
+    where
+        P: Fn(Self::Item) -> bool,
+    {
+        self.data.position(predicate)
+    }
+
+    fn slice_index(&self, count: usize) -> Result<usize, Needed> {
+        self.data.slice_index(count)
+    }
+}
+
+impl<'i, I> InputLength for Located<'i, I>
+where
+    I: ?Sized,
+    &'i I: InputLength,
+{
+    fn input_len(&self) -> usize {
+        self.data.input_len()
+    }
+}
+
+impl<'i, I> InputTake for Located<'i, I>
+where
+    I: ?Sized,
+    Self: Slice<RangeFrom<usize>> + Slice<RangeTo<usize>>,
+{
+    fn take(&self, count: usize) -> Self {
+        self.slice(..count)
+    }
+
+    fn take_split(&self, count: usize) -> (Self, Self) {
+        (self.slice(count..), self.slice(..count))
+    }
+}
+
+impl<'i, I> InputTakeAtPosition for Located<'i, I>
+where
+    I: ?Sized,
+    &'i I: InputIter + InputLength + InputTakeAtPosition,
+    Self: Slice<RangeFrom<usize>> + Slice<RangeTo<usize>>,
+{
+    type Item = <&'i I as InputIter>::Item;
+
+    fn split_at_position_complete<P, E>(&self, predicate: P) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        match self.split_at_position(predicate) {
+            Err(ErrorMode::Incomplete(_)) => Ok(self.take_split(self.input_len())),
+            result => result,
+        }
+    }
+
+    fn split_at_position<P, E>(&self, predicate: P) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        match self.data.position(predicate) {
+            Some(n) => Ok(self.take_split(n)),
+            None => Err(ErrorMode::Incomplete(Needed::new(1))),
+        }
+    }
+
+    fn split_at_position1<P, E>(&self, predicate: P, kind: ErrorKind) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        match self.data.position(predicate) {
+            Some(0) => Err(ErrorMode::Error(E::from_error_kind(*self, kind))),
+            Some(n) => Ok(self.take_split(n)),
+            None => Err(ErrorMode::Incomplete(Needed::new(1))),
+        }
+    }
+
+    fn split_at_position1_complete<P, E>(
+        &self,
+        predicate: P,
+        kind: ErrorKind,
+    ) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        match self.data.position(predicate) {
+            Some(0) => Err(ErrorMode::Error(E::from_error_kind(*self, kind))),
+            Some(n) => Ok(self.take_split(n)),
+            None => {
+                if self.data.input_len() == 0 {
+                    Err(ErrorMode::Error(E::from_error_kind(*self, kind)))
+                }
+                else {
+                    Ok(self.take_split(self.input_len()))
+                }
+            }
+        }
+    }
+}
+
+impl<'i, I> Location for Located<'i, I>
+where
+    I: ?Sized,
+{
+    fn location(&self) -> usize {
+        self.location
+    }
+}
+
+impl<'i, I> Offset for Located<'i, I>
+where
+    I: ?Sized,
+{
+    fn offset(&self, other: &Self) -> usize {
+        other.location.saturating_sub(self.location)
+    }
+}
+
+impl<'i, I, R> Slice<R> for Located<'i, I>
+where
+    I: ?Sized,
+    &'i I: AsBytes + Offset + Slice<R> + Slice<RangeTo<usize>>,
+{
+    fn slice(&self, range: R) -> Self {
+        let sliced = self.data.slice(range);
+        let offset = self.data.offset(&sliced);
+        debug_assert!(offset <= self.data.as_bytes().len());
+        Located {
+            data: sliced,
+            location: self.location + offset,
+        }
+    }
+}
+
+/// A sequence of spanned tokens.
+///
+/// Locations are the source locations of tokens rather than indices into the
+/// sequence, so errors and spans refer to the source from which the tokens
+/// were lexed. Offsets and slices are measured in tokens.
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub struct LocatedTokens<'i, T> {
+    tokens: &'i [Spanned<T>],
+    index: usize,
+    end: usize,
+}
+
+impl<'i, T> LocatedTokens<'i, T> {
+    pub fn new(tokens: &'i [Spanned<T>]) -> Self {
+        LocatedTokens {
+            tokens,
+            index: 0,
+            end: tokens.last().map_or(0, |token| token.span.end),
+        }
+    }
+
+    pub fn into_tokens(self) -> &'i [Spanned<T>] {
+        self.tokens
+    }
+}
+
+impl<'i, T> AsRef<[Spanned<T>]> for LocatedTokens<'i, T> {
+    fn as_ref(&self) -> &[Spanned<T>] {
+        self.tokens
+    }
+}
+
+impl<'i, T> Clone for LocatedTokens<'i, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'i, T> Copy for LocatedTokens<'i, T> {}
+
+impl<'i, T> Deref for LocatedTokens<'i, T> {
+    type Target = [Spanned<T>];
+
+    fn deref(&self) -> &Self::Target {
+        self.tokens
+    }
+}
+
+impl<'i, T> From<&'i [Spanned<T>]> for LocatedTokens<'i, T> {
+    fn from(tokens: &'i [Spanned<T>]) -> Self {
+        LocatedTokens::new(tokens)
+    }
+}
+
+impl<'i, T> InputIter for LocatedTokens<'i, T> {
+    type Item = &'i Spanned<T>;
+    type Iter = iter::Enumerate<slice::Iter<'i, Spanned<T>>>;
+    type IterElem = slice::Iter<'i, Spanned<T>>;
+
+    fn iter_indices(&self) -> Self::Iter {
+        self.tokens.iter().enumerate()
+    }
+
+    fn iter_elements(&self) -> Self::IterElem {
+        self.tokens.iter()
+    }
+
+    fn position<P>(&self, predicate: P) -> Option<usize>
+    where
+        P: Fn(Self::Item) -> bool,
+    {
+        self.tokens.iter().position(predicate)
+    }
+
+    fn slice_index(&self, count: usize) -> Result<usize, Needed> {
+        if self.tokens.len() >= count {
+            Ok(count)
+        }
+        else {
+            Err(Needed::new(count - self.tokens.len()))
+        }
+    }
+}
+
+impl<'i, T> InputLength for LocatedTokens<'i, T> {
+    fn input_len(&self) -> usize {
+        self.tokens.len()
+    }
+}
+
+impl<'i, T> InputTake for LocatedTokens<'i, T> {
+    fn take(&self, count: usize) -> Self {
+        self.slice(..count)
+    }
+
+    fn take_split(&self, count: usize) -> (Self, Self) {
+        (self.slice(count..), self.slice(..count))
+    }
+}
+
+impl<'i, T> InputTakeAtPosition for LocatedTokens<'i, T> {
+    type Item = &'i Spanned<T>;
+
+    fn split_at_position_complete<P, E>(&self, predicate: P) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        match self.split_at_position(predicate) {
+            Err(ErrorMode::Incomplete(_)) => Ok(self.take_split(self.input_len())),
+            result => result,
+        }
+    }
+
+    fn split_at_position<P, E>(&self, predicate: P) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        match self.position(predicate) {
+            Some(n) => Ok(self.take_split(n)),
+            None => Err(ErrorMode::Incomplete(Needed::new(1))),
+        }
+    }
+
+    fn split_at_position1<P, E>(&self, predicate: P, kind: ErrorKind) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        match self.position(predicate) {
+            Some(0) => Err(ErrorMode::Error(E::from_error_kind(*self, kind))),
+            Some(n) => Ok(self.take_split(n)),
+            None => Err(ErrorMode::Incomplete(Needed::new(1))),
+        }
+    }
+
+    fn split_at_position1_complete<P, E>(
+        &self,
+        predicate: P,
+        kind: ErrorKind,
+    ) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        match self.position(predicate) {
+            Some(0) => Err(ErrorMode::Error(E::from_error_kind(*self, kind))),
+            Some(n) => Ok(self.take_split(n)),
+            None => {
+                if self.tokens.is_empty() {
+                    Err(ErrorMode::Error(E::from_error_kind(*self, kind)))
+                }
+                else {
+                    Ok(self.take_split(self.input_len()))
+                }
+            }
+        }
+    }
+}
+
+impl<'i, T> Location for LocatedTokens<'i, T> {
+    fn location(&self) -> usize {
+        self.tokens
+            .first()
+            .map_or(self.end, |token| token.span.start)
+    }
+}
+
+impl<'i, T> Offset for LocatedTokens<'i, T> {
+    fn offset(&self, other: &Self) -> usize {
+        other.index.saturating_sub(self.index)
+    }
+}
+
+impl<'i, T, R> Slice<R> for LocatedTokens<'i, T>
+where
+    &'i [Spanned<T>]: Slice<R>,
+{
+    fn slice(&self, range: R) -> Self {
+        let sliced = self.tokens.slice(range);
+        let offset = (sliced.as_ptr() as usize - self.tokens.as_ptr() as usize)
+            / mem::size_of::<Spanned<T>>();
+        let end = self
+            .tokens
+            .get(offset + sliced.len())
+            .map_or(self.end, |token| token.span.start);
+        LocatedTokens {
+            tokens: sliced,
+            index: self.index + offset,
+            end,
+        }
+    }
+}
+
+pub struct MapWithLocation<P, F, O> {
+    parser: P,
+    f: F,
+    phantom: PhantomData<O>,
+}
+
+impl<I, O1, O2, E, P, F> Parser<I, O2, E> for MapWithLocation<P, F, O1>
+where
+    I: Location,
+    P: Parser<I, O1, E>,
+    F: FnMut(usize, O1) -> O2,
+{
+    fn parse(&mut self, input: I) -> IResult<I, O2, E> {
+        let location = input.location();
+        let (remaining, output) = self.parser.parse(input)?;
+        Ok((remaining, (self.f)(location, output)))
+    }
+}
+
+/// Counts of the visits of parsers to locations in an input. See `metered`.
+///
+/// Metrics are shared rather than stored in the state of `Stateful` input,
+/// because state is discarded when a parser backtracks.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metrics {
+    visits: HashMap<usize, usize>,
+    furthest: Option<usize>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    pub fn visits(&self, location: usize) -> usize {
+        self.visits.get(&location).copied().unwrap_or(0)
+    }
+
+    /// Gets the furthest location at which a parser has succeeded.
+    ///
+    /// This is the furthest end of the input consumed by a parser rather than
+    /// the furthest location at which a parser has been applied.
+    pub fn furthest(&self) -> Option<usize> {
+        self.furthest
+    }
+
+    /// Gets the number of visits to a location after the first.
+    pub fn revisits(&self, location: usize) -> usize {
+        self.visits(location).saturating_sub(1)
+    }
+
+    /// Gets locations and their revisit counts, ordered from most to least
+    /// revisited.
+    pub fn hot_locations(&self) -> Vec<(usize, usize)> {
+        let mut locations: Vec<_> = self
+            .visits
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .map(|(location, count)| (*location, count - 1))
+            .collect();
+        locations.sort_by(|left, right| right.1.cmp(&left.1).then(left.0.cmp(&right.0)));
+        locations
+    }
+
+    fn reach(&mut self, location: usize) {
+        self.furthest = self.furthest.max(Some(location));
+    }
+
+    fn visit(&mut self, location: usize) {
+        *self.visits.entry(location).or_insert(0) += 1;
+    }
+}
+
+/// A token that is missing from input, such as one inserted during error
+/// recovery. See `missing_token_span`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Missing {
+    pub label: &'static str,
+}
+
+/// An identifier of a node derived from its kind and span. See
+/// `with_node_id`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct NodeId(u64);
+
+impl NodeId {
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A mapping from offsets in transformed input to offsets in the original
+/// input.
+///
+/// An empty map is the identity, which is appropriate for length-preserving
+/// transformations such as ASCII case folding. Transformations that change
+/// lengths push segments that associate spans of the transformed input with
+/// spans of the original input. Offsets between or after segments are mapped
+/// relative to the end of the preceding segment.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct OffsetMap {
+    segments: Vec<(Range<usize>, Range<usize>)>,
+}
+
+impl OffsetMap {
+    pub fn new() -> Self {
+        OffsetMap::default()
+    }
+
+    /// Associates a span of the transformed input with a span of the original
+    /// input.
+    ///
+    /// Segments must be pushed in order.
+    pub fn push(&mut self, transformed: Range<usize>, original: Range<usize>) {
+        self.segments.push((transformed, original));
+    }
+
+    pub fn original_offset(&self, offset: usize) -> usize {
+        let n = self
+            .segments
+            .partition_point(|(transformed, _)| transformed.start <= offset);
+        match n.checked_sub(1).map(|n| &self.segments[n]) {
+            Some((transformed, original)) if offset < transformed.end => {
+                original.start + (offset - transformed.start).min(original.len())
+            }
+            Some((transformed, original)) => original.end + (offset - transformed.end),
+            None => offset,
+        }
+    }
+}
+
+/// An owned parse error with a span and message.
+///
+/// Unlike nom's errors, this error does not borrow input and implements
+/// `std::error::Error`, so it can be propagated with `?`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct ParseFailure {
+    pub span: Range<usize>,
+    pub message: String,
+}
+
+impl Display for ParseFailure {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at {}..{}",
+            self.message, self.span.start, self.span.end
+        )
+    }
+}
+
+impl Error for ParseFailure {}
+
+impl From<LocatedError> for ParseFailure {
+    fn from(error: LocatedError) -> Self {
+        let mut message = error.kind.description().to_owned();
+        for (location, label) in error.context.iter() {
+            message.push_str(&format!(", in {} at {}", label, location));
+        }
+        ParseFailure {
+            span: error.span,
+            message,
+        }
+    }
+}
+
+/// The severity of a diagnostic. See `Diagnostics`.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Significance {
+    Significant,
+    Insignificant,
+}
+
+/// Soft keywords that are enabled in the current context, typically as the
+/// state of `Stateful` input. See `soft_keyword`.
+///
+/// Use `with_reset_state` to enable soft keywords only within a parser.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct SoftKeywords {
+    words: Vec<&'static str>,
+}
+
+impl SoftKeywords {
+    pub fn new() -> Self {
+        SoftKeywords::default()
+    }
+
+    pub fn enable(&mut self, word: &'static str) {
+        if !self.is_enabled(word) {
+            self.words.push(word);
+        }
+    }
+
+    pub fn disable(&mut self, word: &'static str) {
+        self.words.retain(|enabled| *enabled != word);
+    }
+
+    pub fn is_enabled(&self, word: &str) -> bool {
+        self.words.contains(&word)
+    }
+}
+
+impl<const N: usize> From<[&'static str; N]> for SoftKeywords {
+    fn from(words: [&'static str; N]) -> Self {
+        let mut keywords = SoftKeywords::new();
+        for word in words {
+            keywords.enable(word);
+        }
+        keywords
+    }
+}
+
+/// An arena of spanned values that are referred to by `Handle`s. See
+/// `arena_alloc`.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SpanArena<T> {
+    items: Vec<Spanned<T>>,
+}
+
+impl<T> SpanArena<T> {
+    pub fn new() -> Self {
+        SpanArena { items: Vec::new() }
+    }
+
+    pub fn alloc(&mut self, item: Spanned<T>) -> Handle {
+        self.items.push(item);
+        Handle(self.items.len() - 1)
+    }
+
+    pub fn get(&self, handle: Handle) -> Option<&Spanned<T>> {
+        self.items.get(handle.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl<T> Default for SpanArena<T> {
+    fn default() -> Self {
+        SpanArena::new()
+    }
+}
+
+/// Spans recorded by key, typically as the state of `Stateful` input. See
+/// `record_span` and `record!`.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct SpanCollector {
+    spans: Vec<(&'static str, Range<usize>)>,
+}
+
+impl SpanCollector {
+    pub fn new() -> Self {
+        SpanCollector::default()
+    }
+
+    /// Gets the most recently recorded span for a key.
+    pub fn get(&self, key: &str) -> Option<Range<usize>> {
+        self.spans
+            .iter()
+            .rev()
+            .find(|(recorded, _)| *recorded == key)
+            .map(|(_, span)| span.clone())
+    }
+
+    pub fn iter(&self) -> impl '_ + Iterator<Item = (&'static str, Range<usize>)> {
+        self.spans.iter().cloned()
+    }
+
+    pub fn record(&mut self, key: &'static str, span: Range<usize>) {
+        self.spans.push((key, span));
+    }
+}
+
+/// A mapping from spans in a source to spans in code generated from it.
+///
+/// This is intended for tools that emit code from a parsed source and must
+/// refer back from the generated code to the source, or vice versa. Spans of
+/// `proc_macro2::Span` cannot be constructed from arbitrary byte ranges on
+/// stable Rust, so this only provides the offset arithmetic: spans mapped by
+/// this type must be associated with tokens by other means, such as
+/// `Span::call_site` and `Span::located_at`.
+#[cfg(feature = "codegen")]
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct SpanMap {
+    entries: Vec<(Range<usize>, Range<usize>)>,
+}
+
+#[cfg(feature = "codegen")]
+impl SpanMap {
+    pub fn new() -> Self {
+        SpanMap::default()
+    }
+
+    /// Associates a span in the source with a span in the generated code.
+    pub fn insert(&mut self, source: Range<usize>, generated: Range<usize>) {
+        self.entries.push((source, generated));
+    }
+
+    /// Maps a span in the source into the generated code.
+    ///
+    /// The span is mapped relative to the smallest source span that contains
+    /// it and is truncated to the associated generated span.
+    pub fn to_generated(&self, span: Range<usize>) -> Option<Range<usize>> {
+        SpanMap::map(self.entries.iter().cloned(), span)
+    }
+
+    /// Maps a span in the generated code into the source.
+    ///
+    /// See `to_generated`.
+    pub fn to_source(&self, span: Range<usize>) -> Option<Range<usize>> {
+        SpanMap::map(
+            self.entries
+                .iter()
+                .map(|(source, generated)| (generated.clone(), source.clone())),
+            span,
+        )
+    }
+
+    fn map(
+        entries: impl Iterator<Item = (Range<usize>, Range<usize>)>,
+        span: Range<usize>,
+    ) -> Option<Range<usize>> {
+        entries
+            .filter(|(from, _)| from.start <= span.start && span.end <= from.end)
+            .min_by_key(|(from, _)| from.len())
+            .map(|(from, to)| {
+                let start = (to.start + (span.start - from.start)).min(to.end);
+                let end = (to.start + (span.end - from.start)).min(to.end);
+                start..end
+            })
+    }
+}
+
+/// Spans of nodes by kind, typically as the state of `Stateful` input. See
+/// `record_node`.
+///
+/// This is intended for snapshot testing of grammars.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct SpanReport {
+    nodes: Vec<(&'static str, Range<usize>)>,
+}
+
+impl SpanReport {
+    pub fn new() -> Self {
+        SpanReport::default()
+    }
+
+    pub fn iter(&self) -> impl '_ + Iterator<Item = (&'static str, Range<usize>)> {
+        self.nodes.iter().cloned()
+    }
+
+    pub fn record(&mut self, kind: &'static str, span: Range<usize>) {
+        self.nodes.push((kind, span));
+    }
+
+    /// Gets a deterministic rendering of the nodes with one node per line.
+    ///
+    /// Nodes are sorted by the start of their spans, then from the longest to
+    /// the shortest span so that enclosing nodes precede the nodes that they
+    /// enclose, and then by kind.
+    pub fn to_snapshot_string(&self) -> String {
+        let mut nodes = self.nodes.clone();
+        nodes.sort_by(|(left, a), (right, b)| {
+            a.start
+                .cmp(&b.start)
+                .then(b.end.cmp(&a.end))
+                .then(left.cmp(right))
+        });
+        nodes
+            .into_iter()
+            .map(|(kind, span)| format!("{}..{} {}\n", span.start, span.end, kind))
+            .collect()
+    }
+}
+
+/// A sink that writes spans as JSON Lines. See `emit_span`.
+///
+/// Each span is written as an object with the fields `kind`, `start`, and
+/// `end` followed by a newline. Writing stops at the first error, which is
+/// reported by `into_inner`.
+#[cfg(feature = "serde")]
+#[derive(Debug)]
+pub struct SpanSink<W>
+where
+    W: Write,
+{
+    writer: W,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "serde")]
+impl<W> SpanSink<W>
+where
+    W: Write,
+{
+    pub fn new(writer: W) -> Self {
+        SpanSink {
+            writer,
+            error: None,
+        }
+    }
+
+    /// Gets the writer or the first error that occurred while writing.
+    pub fn into_inner(self) -> io::Result<W> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(self.writer),
+        }
+    }
+
+    fn emit(&mut self, kind: &str, span: Range<usize>) {
+        #[derive(Serialize)]
+        struct Record<'k> {
+            kind: &'k str,
+            start: usize,
+            end: usize,
+        }
+
+        if self.error.is_some() {
+            return;
+        }
+        let record = Record {
+            kind,
+            start: span.start,
+            end: span.end,
+        };
+        let result = serde_json::to_writer(&mut self.writer, &record)
+            .map_err(io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"));
+        if let Err(error) = result {
+            self.error = Some(error);
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Spanned<T> {
+    pub span: Range<usize>,
+    pub value: T,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(span: Range<usize>, value: T) -> Self {
+        Spanned { span, value }
+    }
+
+    pub fn into_value(self) -> T {
+        self.value
+    }
+}
+
+impl<T> DebugTree for Spanned<T>
+where
+    T: DebugTree,
+{
+    fn label(&self) -> String {
+        self.value.label()
+    }
+
+    fn span(&self) -> Option<Range<usize>> {
+        Some(self.span.clone())
+    }
+
+    fn children(&self) -> Vec<&dyn DebugTree> {
+        self.value.children()
+    }
+}
+
+impl<T> Display for Spanned<T>
+where
+    T: Display,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.value, f)
+    }
+}
+
+impl<T> RemapSpans for Spanned<T>
+where
+    T: RemapSpans,
+{
+    fn remap<F>(&mut self, f: &F)
+    where
+        F: Fn(Range<usize>) -> Range<usize>,
+    {
+        self.span = f(self.span.clone());
+        self.value.remap(f);
+    }
+}
+
+/// Input with associated state.
+///
+/// Comparisons and hashing consider both the input and its state. Use
+/// [`Stateful::fragment_eq`] to compare input only.
+///
+/// Input traits and `Location` are forwarded to the wrapped input, so
+/// `Stateful` can be nested to compose layers of state, such as
+/// `Stateful<Stateful<Located<'_, str>, A>, B>`, and locations and spans are
+/// those of the innermost input.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Stateful<I, T> {
+    data: I,
+    pub state: T,
+}
+
+impl<I, T> Stateful<I, T> {
+    pub fn new(data: I, state: T) -> Self {
+        Stateful { data, state }
+    }
+
+    pub fn fragment_eq<U>(&self, other: &Stateful<I, U>) -> bool
+    where
+        I: PartialEq,
+    {
+        self.data == other.data
+    }
+
+    /// Replaces the state and returns the previous state.
+    ///
+    /// State is cloned into both halves when input is split (e.g., by
+    /// `take_split`), so replacing the state only affects this input and any
+    /// input subsequently derived from it.
+    pub fn replace_state(&mut self, state: T) -> T {
+        mem::replace(&mut self.state, state)
+    }
+
+    fn clone_map<F>(&self, mut f: F) -> Self
+    where
+        T: Clone,
+        F: FnMut(&I) -> I,
+    {
+        Stateful {
+            data: f(&self.data),
+            state: self.state.clone(),
+        }
+    }
+
+    fn clone_map_result<E, F>(&self, f: F) -> IResult<Self, Self, E>
+    where
+        E: ParseError<Self>,
+        T: Clone,
+        F: FnOnce(&I) -> IResult<I, I>,
+    {
+        let map_error = |error: NomError<I>| {
+            E::from_error_kind(
+                Stateful {
+                    data: error.input,
+                    state: self.state.clone(),
+                },
+                error.code,
+            )
+        };
+        f(&self.data)
+            .map(|(remaining, output)| {
+                (
+                    Stateful {
+                        data: remaining,
+                        state: self.state.clone(),
+                    },
+                    Stateful {
+                        data: output,
+                        state: self.state.clone(),
+                    },
+                )
+            })
+            .map_err(|error| match error {
+                ErrorMode::Error(error) => ErrorMode::Error(map_error(error)),
+                ErrorMode::Failure(error) => ErrorMode::Failure(map_error(error)),
+                ErrorMode::Incomplete(needed) => ErrorMode::Incomplete(needed),
+            })
+    }
+}
+
+impl<'i, I, T> Stateful<Located<'i, I>, T>
+where
+    I: ?Sized,
+{
+    pub fn as_located_ref(&self) -> Stateful<Located<'i, I::Target>, T>
+    where
+        I: Deref,
+        T: Clone,
+    {
+        Stateful {
+            data: self.data.as_located_ref(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<I, C, S> Stateful<I, Context<C, S>> {
+    pub fn config(&self) -> &C {
+        self.state.config()
+    }
+
+    pub fn state_mut(&mut self) -> &mut S {
+        &mut self.state.state
+    }
+}
+
+impl<I> Stateful<I, LineColumn> {
+    pub fn line(&self) -> usize {
+        self.state.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.state.column
+    }
+}
+
+impl<I, T> AsBytes for Stateful<I, T>
+where
+    I: AsBytes,
+{
+    fn as_bytes(&self) -> &[u8] {
+        self.data.as_bytes()
+    }
+}
+
+impl<I, T> AsRef<[u8]> for Stateful<I, T>
+where
+    I: AsRef<[u8]>,
+{
+    fn as_ref(&self) -> &[u8] {
+        self.data.as_ref()
+    }
+}
+
+impl<I, T> AsRef<str> for Stateful<I, T>
+where
+    I: AsRef<str>,
+{
+    fn as_ref(&self) -> &str {
+        self.data.as_ref()
+    }
+}
+
+impl<I, T> AsRef<I> for Stateful<I, T> {
+    fn as_ref(&self) -> &I {
+        &self.data
+    }
+}
+
+impl<I, T, U> Compare<U> for Stateful<I, T>
+where
+    I: Compare<U>,
+{
+    fn compare(&self, other: U) -> CompareResult {
+        self.data.compare(other)
+    }
+
+    fn compare_no_case(&self, other: U) -> CompareResult {
+        self.data.compare_no_case(other)
+    }
+}
+
+impl<I, T> Deref for Stateful<I, T> {
+    type Target = I;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_ref()
+    }
+}
+
+impl<I, T> Display for Stateful<I, T>
+where
+    I: Display,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.data, f)
+    }
+}
+
+impl<I, T> ExtendInto for Stateful<I, T>
+where
+    I: ExtendInto,
+{
+    type Item = I::Item;
+    type Extender = I::Extender;
+
+    fn new_builder(&self) -> Self::Extender {
+        self.data.new_builder()
+    }
+
+    fn extend_into(&self, extender: &mut Self::Extender) {
+        self.data.extend_into(extender)
+    }
+}
+
+impl<I, T> InputIter for Stateful<I, T>
+where
+    I: InputIter,
+{
+    type Item = I::Item;
+    type Iter = I::Iter;
+    type IterElem = I::IterElem;
+
+    fn iter_indices(&self) -> Self::Iter {
+        self.data.iter_indices()
+    }
+
+    fn iter_elements(&self) -> Self::IterElem {
+        self.data.iter_elements()
+    }
+
+    fn position<P>(&self, predicate: P) -> Option<usize>
+    where
+        P: Fn(Self::Item) -> bool,
+    {
+        self.data.position(predicate)
+    }
+
+    fn slice_index(&self, count: usize) -> Result<usize, Needed> {
+        self.data.slice_index(count)
+    }
+}
+
+impl<I, T> InputLength for Stateful<I, T>
+where
+    I: InputLength,
+{
+    fn input_len(&self) -> usize {
+        self.data.input_len()
+    }
+}
+
+impl<I, T> InputTake for Stateful<I, T>
+where
+    I: InputTake,
+    T: Clone,
+{
+    fn take(&self, count: usize) -> Self {
+        self.clone_map(move |data| data.take(count))
+    }
+
+    fn take_split(&self, count: usize) -> (Self, Self) {
+        let (left, right) = self.data.take_split(count);
+        (
+            Stateful {
+                data: left,
+                state: self.state.clone(),
+            },
+            Stateful {
+                data: right,
+                state: self.state.clone(),
+            },
+        )
+    }
+}
+
+impl<I, T> InputTakeAtPosition for Stateful<I, T>
+where
+    I: InputTakeAtPosition,
+    T: Clone,
+{
+    type Item = <I as InputTakeAtPosition>::Item;
+
+    fn split_at_position_complete<P, E>(&self, predicate: P) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        self.clone_map_result(move |data| data.split_at_position_complete(predicate))
+    }
+
+    fn split_at_position<P, E>(&self, predicate: P) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        self.clone_map_result(move |data| data.split_at_position(predicate))
+    }
+
+    fn split_at_position1<P, E>(&self, predicate: P, kind: ErrorKind) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        self.clone_map_result(move |data| data.split_at_position1(predicate, kind))
+    }
+
+    fn split_at_position1_complete<P, E>(
+        &self,
+        predicate: P,
+        kind: ErrorKind,
+    ) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        self.clone_map_result(move |data| data.split_at_position1_complete(predicate, kind))
+    }
+}
+
+impl<I, T> FullLocation for Stateful<I, T>
+where
+    I: FullLocation,
+{
+    fn full_location(&self) -> (usize, usize, usize) {
+        self.data.full_location()
+    }
+}
+
+impl<I, T> Location for Stateful<I, T>
+where
+    I: Location,
+{
+    fn location(&self) -> usize {
+        self.data.location()
+    }
+}
+
+impl<I, T> Offset for Stateful<I, T>
+where
+    I: Offset,
+{
+    fn offset(&self, other: &Self) -> usize {
+        self.data.offset(&other.data)
+    }
+}
+
+impl<I, T, R> Slice<R> for Stateful<I, T>
+where
+    I: Slice<R>,
+    T: Clone,
+{
+    fn slice(&self, range: R) -> Self {
+        let data = self.data.slice(range);
+        Stateful {
+            data,
+            state: self.state.clone(),
+        }
+    }
+}
+
+/// The spans of the definition and references of a symbol. See
+/// `SymbolTable`.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Symbol {
+    pub definition: Option<Range<usize>>,
+    pub references: Vec<Range<usize>>,
+}
+
+/// Symbols by name, typically as the state of `Stateful` input. See `define`
+/// and `reference`.
+///
+/// Only the first definition of a symbol is recorded.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SymbolTable {
+    pub symbols: HashMap<String, Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        SymbolTable::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.get(name)
+    }
+}
+
+/// A fragment of input and its location, line, and column.
+///
+/// Lines and columns are zero-based and columns are measured in bytes. Both
+/// are computed incrementally as input is consumed, so the source is never
+/// re-scanned.
+#[derive(Debug, Eq, Hash, PartialEq)]
+pub struct Tracked<'i, I>
+where
+    I: ?Sized,
+{
+    data: &'i I,
+    location: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'i, I> Tracked<'i, I>
+where
+    I: ?Sized,
+{
+    pub fn into_data(self) -> &'i I {
+        self.data
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn line_column(&self) -> LineColumn {
+        LineColumn {
+            line: self.line,
+            column: self.column,
+        }
+    }
+}
+
+impl<'i, I> AsBytes for Tracked<'i, I>
+where
+    I: ?Sized,
+    &'i I: AsBytes,
+{
+    fn as_bytes(&self) -> &[u8] {
+        self.data.as_bytes()
+    }
+}
+
+impl<'i, I> AsRef<I> for Tracked<'i, I>
+where
+    I: ?Sized,
+{
+    fn as_ref(&self) -> &I {
+        self.data
+    }
+}
+
+impl<'i, I> Clone for Tracked<'i, I>
+where
+    I: ?Sized,
+{
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'i, I, U> Compare<U> for Tracked<'i, I>
+where
+    I: ?Sized,
+    &'i I: Compare<U>,
+{
+    fn compare(&self, other: U) -> CompareResult {
+        self.data.compare(other)
+    }
+
+    fn compare_no_case(&self, other: U) -> CompareResult {
+        self.data.compare_no_case(other)
+    }
+}
+
+impl<'i, I> Copy for Tracked<'i, I> where I: ?Sized {}
+
+impl<'i, I> Deref for Tracked<'i, I>
+where
+    I: ?Sized,
+{
+    type Target = I;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_ref()
+    }
+}
+
+impl<'i, I> Display for Tracked<'i, I>
+where
+    I: Display + ?Sized,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(self.data, f)
+    }
+}
+
+impl<'i, I> ExtendInto for Tracked<'i, I>
+where
+    I: ?Sized,
+    &'i I: ExtendInto,
+{
+    type Item = <&'i I as ExtendInto>::Item;
+    type Extender = <&'i I as ExtendInto>::Extender;
+
+    fn new_builder(&self) -> Self::Extender {
+        self.data.new_builder()
+    }
+
+    fn extend_into(&self, extender: &mut Self::Extender) {
+        self.data.extend_into(extender)
+    }
+}
+
+impl<'i, I> From<&'i I> for Tracked<'i, I>
+where
+    I: ?Sized,
+{
+    fn from(data: &'i I) -> Self {
+        Tracked {
+            data,
+            location: 0,
+            line: 0,
+            column: 0,
+        }
+    }
+}
+
+impl<'i, I> FullLocation for Tracked<'i, I>
+where
+    I: ?Sized,
+{
+    fn full_location(&self) -> (usize, usize, usize) {
+        (self.location, self.line, self.column)
+    }
+}
+
+impl<'i, I> InputIter for Tracked<'i, I>
+where
+    I: ?Sized,
+    &'i I: InputIter,
+{
+    type Item = <&'i I as InputIter>::Item;
+    type Iter = <&'i I as InputIter>::Iter;
+    type IterElem = <&'i I as InputIter>::IterElem;
+
+    fn iter_indices(&self) -> Self::Iter {
+        self.data.iter_indices()
+    }
+
+    fn iter_elements(&self) -> Self::IterElem {
+        self.data.iter_elements()
+    }
+
+    fn position<P>(&self, predicate: P) -> Option<usize>
+    where
+        P: Fn(Self::Item) -> bool,
+    {
+        self.data.position(predicate)
+    }
+
+    fn slice_index(&self, count: usize) -> Result<usize, Needed> {
+        self.data.slice_index(count)
+    }
+}
+
+impl<'i, I> InputLength for Tracked<'i, I>
+where
+    I: ?Sized,
+    &'i I: InputLength,
+{
+    fn input_len(&self) -> usize {
+        self.data.input_len()
+    }
+}
+
+impl<'i, I> InputTake for Tracked<'i, I>
+where
+    I: ?Sized,
+    Self: Slice<RangeFrom<usize>> + Slice<RangeTo<usize>>,
+{
+    fn take(&self, count: usize) -> Self {
+        self.slice(..count)
+    }
+
+    fn take_split(&self, count: usize) -> (Self, Self) {
+        (self.slice(count..), self.slice(..count))
+    }
+}
+
+impl<'i, I> InputTakeAtPosition for Tracked<'i, I>
+where
+    I: ?Sized,
+    &'i I: InputIter + InputLength + InputTakeAtPosition,
+    Self: Slice<RangeFrom<usize>> + Slice<RangeTo<usize>>,
+{
+    type Item = <&'i I as InputIter>::Item;
+
+    fn split_at_position_complete<P, E>(&self, predicate: P) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        match self.split_at_position(predicate) {
+            Err(ErrorMode::Incomplete(_)) => Ok(self.take_split(self.input_len())),
+            result => result,
+        }
+    }
+
+    fn split_at_position<P, E>(&self, predicate: P) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        match self.data.position(predicate) {
+            Some(n) => Ok(self.take_split(n)),
+            None => Err(ErrorMode::Incomplete(Needed::new(1))),
+        }
+    }
+
+    fn split_at_position1<P, E>(&self, predicate: P, kind: ErrorKind) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        match self.data.position(predicate) {
+            Some(0) => Err(ErrorMode::Error(E::from_error_kind(*self, kind))),
+            Some(n) => Ok(self.take_split(n)),
+            None => Err(ErrorMode::Incomplete(Needed::new(1))),
+        }
+    }
+
+    fn split_at_position1_complete<P, E>(
+        &self,
+        predicate: P,
+        kind: ErrorKind,
+    ) -> IResult<Self, Self, E>
+    where
+        P: Fn(Self::Item) -> bool,
+        E: ParseError<Self>,
+    {
+        match self.data.position(predicate) {
+            Some(0) => Err(ErrorMode::Error(E::from_error_kind(*self, kind))),
+            Some(n) => Ok(self.take_split(n)),
+            None => {
+                if self.data.input_len() == 0 {
+                    Err(ErrorMode::Error(E::from_error_kind(*self, kind)))
+                }
+                else {
+                    Ok(self.take_split(self.input_len()))
+                }
+            }
+        }
+    }
+}
+
+impl<'i, I> Location for Tracked<'i, I>
+where
+    I: ?Sized,
+{
+    fn location(&self) -> usize {
+        self.location
+    }
+}
+
+impl<'i, I> Offset for Tracked<'i, I>
+where
+    I: ?Sized,
+{
+    fn offset(&self, other: &Self) -> usize {
+        other.location.saturating_sub(self.location)
+    }
+}
+
+impl<'i, I, R> Slice<R> for Tracked<'i, I>
+where
+    I: ?Sized,
+    &'i I: AsBytes + Offset + Slice<R> + Slice<RangeTo<usize>>,
+{
+    fn slice(&self, range: R) -> Self {
+        let sliced = self.data.slice(range);
+        let offset = self.data.offset(&sliced);
+        let consumed = &self.data.as_bytes()[..offset];
+        let (line, column) = match consumed.iter().rposition(|&byte| byte == b'\n') {
+            Some(n) => (
+                self.line + consumed.iter().filter(|&&byte| byte == b'\n').count(),
+                offset - (n + 1),
+            ),
+            None => (self.line, self.column + offset),
+        };
+        Tracked {
+            data: sliced,
+            location: self.location + offset,
+            line,
+            column,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Trivia {
+    Whitespace,
+    LineComment,
+}
+
+/// An unexpected token and the labels of the tokens that were expected in
+/// its place. See `unexpected`.
+///
+/// The token is empty at the end of input.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Unexpected {
+    pub token: String,
+    pub span: Range<usize>,
+    pub expected: Vec<&'static str>,
+}
+
+impl Display for Unexpected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.token.is_empty() {
+            write!(f, "unexpected end of input")?;
+        }
+        else {
+            write!(f, "unexpected `{}`", self.token)?;
+        }
+        write!(
+            f,
+            ", expected one of {} at {}",
+            self.expected.join(", "),
+            self.span.start,
+        )
+    }
+}
+
+pub fn all_consuming_span<I, O, E, F>(mut parser: F) -> impl FnMut(I) -> IResult<I, O, E>
+where
+    I: InputLength + Location,
+    E: SpanError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        let (remaining, output) = parser.parse(input)?;
+        let n = remaining.input_len();
+        if n == 0 {
+            Ok((remaining, output))
+        }
+        else {
+            let start = remaining.location();
+            Err(ErrorMode::Error(E::from_span(
+                remaining,
+                start..(start + n),
+                ErrorKind::Eof,
+            )))
+        }
+    }
+}
+
+/// Applies the first matching parser in a choice of labeled parsers.
+///
+/// The choice is a tuple of pairs of a label and a parser. When all parsers
+/// fail, the parser fails with an `Expected` that refers to all labels and the
+/// location at which the choice was attempted.
+pub fn alt_expected<I, O, E, A>(mut branches: A) -> impl FnMut(I) -> IResult<I, O, E>
+where
+    I: Clone + Location,
+    E: FromExternalError<I, Expected> + ParseError<I>,
+    A: LabeledAlt<I, O, E>,
+{
+    move |input: I| match branches.choice(input.clone()) {
+        Ok((remaining, (_, output))) => Ok((remaining, output)),
+        Err(ErrorMode::Error(_)) => {
+            let expected = Expected {
+                location: input.location(),
+                labels: branches.labels(),
+            };
+            Err(ErrorMode::Error(E::from_external_error(
+                input,
+                ErrorKind::Alt,
+                expected,
+            )))
+        }
+        Err(mode) => Err(mode),
+    }
+}
+
+/// Applies a parser and allocates its output and the span of the input that
+/// it consumed in an arena.
+///
+/// Allocations are not undone when an enclosing parser backtracks.
+pub fn arena_alloc<'x, I, O, E, F>(
+    arena: &'x RefCell<SpanArena<O>>,
+    parser: F,
+) -> impl FnMut(I) -> IResult<I, Handle, E> + 'x
+where
+    I: Location + 'x,
+    O: 'x,
+    E: ParseError<I> + 'x,
+    F: Parser<I, O, E> + 'x,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        parser(input).map(|(remaining, (span, output))| {
+            let handle = arena.borrow_mut().alloc(Spanned::new(span, output));
+            (remaining, handle)
+        })
+    }
+}
+
+/// Applies a parser and fails if the input that it consumed is not ASCII.
+///
+/// The error spans the first non-ASCII byte of the consumed input.
+pub fn ascii_only<I, O, E, F>(mut parser: F) -> impl FnMut(I) -> IResult<I, O, E>
+where
+    I: AsBytes + Clone + Location + Offset + Slice<RangeFrom<usize>>,
+    E: SpanError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        let (remaining, output) = parser.parse(input.clone())?;
+        let n = input.offset(&remaining);
+        match input.as_bytes()[..n]
+            .iter()
+            .position(|byte| !byte.is_ascii())
+        {
+            Some(n) => {
+                let start = input.location() + n;
+                Err(ErrorMode::Error(E::from_span(
+                    input.slice(n..),
+                    start..(start + 1),
+                    ErrorKind::Verify,
+                )))
+            }
+            None => Ok((remaining, output)),
+        }
+    }
+}
+
+/// Checks that the spans of items are ordered and do not overlap.
+///
+/// On failure, returns the indices of the first pair of adjacent items that
+/// are out of order or overlap.
+pub fn assert_spans_ordered<T>(items: &[Spanned<T>]) -> Result<(), (usize, usize)> {
+    items
+        .windows(2)
+        .position(|pair| pair[1].span.start < pair[0].span.end)
+        .map_or(Ok(()), |n| Err((n, n + 1)))
+}
+
+/// Parses a pair separated by a gap and gets the span of the gap.
+///
+/// The gap is consumed by a parser that typically skips whitespace or
+/// comments. The span is zero-width when the gap parser consumes no input.
+#[allow(clippy::type_complexity)]
+pub fn between_span<I, O1, O2, O3, E, F, G, H>(
+    mut first: F,
+    gap: G,
+    mut second: H,
+) -> impl FnMut(I) -> IResult<I, (O1, Range<usize>, O3), E>
+where
+    I: Location,
+    E: ParseError<I>,
+    F: Parser<I, O1, E>,
+    G: Parser<I, O2, E>,
+    H: Parser<I, O3, E>,
+{
+    let mut gap = span_range(gap);
+    move |input: I| {
+        let (input, output1) = first.parse(input)?;
+        let (input, (span, _)) = gap(input)?;
+        let (input, output3) = second.parse(input)?;
+        Ok((input, (output1, span, output3)))
+    }
+}
+
+/// Applies a parser from `nom::bits` to `BitLocated` input.
+///
+/// Errors from `parser` are converted to `E` with the same input and kind.
+#[allow(clippy::type_complexity)]
+pub fn bit_located<I, O, E, F>(
+    mut parser: F,
+) -> impl FnMut(BitLocated<I>) -> IResult<BitLocated<I>, O, E>
+where
+    E: ParseError<BitLocated<I>>,
+    F: Parser<(I, usize), O, NomError<(I, usize)>>,
+{
+    move |input: BitLocated<I>| {
+        parser
+            .parse(input.into_inner())
+            .map(|(remaining, output)| (remaining.into(), output))
+            .map_err(|error| error.map(|error| E::from_error_kind(error.input.into(), error.code)))
+    }
+}
+
+/// Applies a parser over `BitLocated` input to byte-level input.
+///
+/// This is like `nom::bits::bits`: input is consumed through the byte that
+/// contains the last bit consumed by `parser`.
+pub fn bits_located<I, O, E, F>(mut parser: F) -> impl FnMut(I) -> IResult<I, O, E>
+where
+    I: Slice<RangeFrom<usize>>,
+    E: ParseError<I>,
+    F: Parser<BitLocated<I>, O, NomError<BitLocated<I>>>,
+{
+    move |input: I| match parser.parse(BitLocated::new(input, 0)) {
+        Ok((remaining, output)) => {
+            let (data, offset) = remaining.into_inner();
+            Ok((data.slice(offset.div_ceil(8)..), output))
+        }
+        Err(ErrorMode::Incomplete(needed)) => {
+            Err(ErrorMode::Incomplete(needed.map(|n| (n.get() / 8) + 1)))
+        }
+        Err(error) => Err(error.map(|error| E::from_error_kind(error.input.data, error.code))),
+    }
+}
+
+pub fn bof<I, E>(input: I) -> IResult<I, I, E>
+where
+    I: Clone + Location,
+    E: ParseError<I>,
+{
+    if input.location() == 0 {
+        Ok((input.clone(), input))
+    }
+    else {
+        Err(ErrorMode::Error(E::from_error_kind(input, ErrorKind::Eof)))
+    }
+}
+
+/// Fails if a parser consumes more than `max` items of input.
+///
+/// Unlike `max_consumed`, the error spans all of the input consumed by the
+/// parser.
+pub fn bounded_token<I, O, E, F>(max: usize, parser: F) -> impl FnMut(I) -> IResult<I, O, E>
+where
+    I: Clone + Location,
+    E: SpanError<I>,
+    F: Parser<I, O, E>,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        let (remaining, (span, output)) = parser(input.clone())?;
+        if span.len() > max {
+            Err(ErrorMode::Error(E::from_span(
+                input,
+                span,
+                ErrorKind::TooLarge,
+            )))
+        }
+        else {
+            Ok((remaining, output))
+        }
+    }
+}
+
+/// Applies a parser and gets its output and the fragment that it matched.
+///
+/// The fragment retains its location, so it can be parsed again later, such
+/// as by another grammar, and yield spans in the coordinates of the original
+/// input.
+pub fn capture_region<I, O, E, F>(mut parser: F) -> impl FnMut(I) -> IResult<I, (O, I), E>
+where
+    I: Clone + Offset + Slice<RangeTo<usize>>,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        let (remaining, output) = parser.parse(input.clone())?;
+        let region = input.slice(..input.offset(&remaining));
+        Ok((remaining, (output, region)))
+    }
+}
+
+/// Matches a character and gets its location.
+pub fn char_located<I, E>(c: char) -> impl Fn(I) -> IResult<I, (usize, char), E>
+where
+    I: InputIter + Location + Slice<RangeFrom<usize>>,
+    <I as InputIter>::Item: AsChar,
+    E: ParseError<I>,
+{
+    move |input: I| {
+        let location = input.location();
+        char(c)(input).map(|(remaining, c)| (remaining, (location, c)))
+    }
+}
+
+/// Classifies all input as significant tokens or insignificant trivia and
+/// gets their spans.
+///
+/// Trivia is attempted before tokens at each location. Fails at the first
+/// location at which neither parser consumes input, so the spans are ordered
+/// and cover the entire input.
+#[allow(clippy::type_complexity)]
+pub fn classify_spans<I, O1, O2, E, F, G>(
+    token: F,
+    trivia: G,
+) -> impl FnMut(I) -> IResult<I, Vec<(Range<usize>, Significance)>, E>
+where
+    I: Clone + InputLength + Location,
+    E: ParseError<I>,
+    F: Parser<I, O1, E>,
+    G: Parser<I, O2, E>,
+{
+    let mut token = span_range(token);
+    let mut trivia = span_range(trivia);
+    move |mut input: I| {
+        let mut spans = Vec::new();
+        while input.input_len() > 0 {
+            let len = input.input_len();
+            let (remaining, span, significance) = match trivia(input.clone()) {
+                Ok((remaining, (span, _))) if remaining.input_len() < len => {
+                    (remaining, span, Significance::Insignificant)
+                }
+                Ok(_) | Err(ErrorMode::Error(_)) => match token(input.clone()) {
+                    Ok((remaining, (span, _))) if remaining.input_len() < len => {
+                        (remaining, span, Significance::Significant)
+                    }
+                    Ok(_) | Err(ErrorMode::Error(_)) => {
+                        return Err(ErrorMode::Error(E::from_error_kind(input, ErrorKind::Alt)));
+                    }
+                    Err(error) => return Err(error),
+                },
+                Err(error) => return Err(error),
+            };
+            spans.push((span, significance));
+            input = remaining;
+        }
+        Ok((input, spans))
+    }
+}
+
+/// Applies a parser and, on failure, gets an error that spans the partial word
+/// at the location of the parser.
+///
+/// The partial word is the run of alphanumeric characters and underscores at
+/// the location of the parser, such as a keyword that is not yet complete.
+/// This is the word under the cursor for completion.
+pub fn completion_context<I, O, E, F>(mut parser: F) -> impl FnMut(I) -> IResult<I, O, E>
+where
+    I: Clone + InputIter + InputLength + Location,
+    <I as InputIter>::Item: AsChar,
+    E: SpanError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        parser.parse(input.clone()).map_err(|error| {
+            let start = input.location();
+            let n = input
+                .position(|item| {
+                    let c = item.as_char();
+                    !(c.is_alphanumeric() || c == '_')
+                })
+                .unwrap_or_else(|| input.input_len());
+            let span_error = || E::from_span(input.clone(), start..(start + n), ErrorKind::Fail);
+            match error {
+                ErrorMode::Error(_) => ErrorMode::Error(span_error()),
+                ErrorMode::Failure(_) => ErrorMode::Failure(span_error()),
+                ErrorMode::Incomplete(needed) => ErrorMode::Incomplete(needed),
+            }
+        })
+    }
+}
+
+/// Applies a parser and gets the number of bytes of input that it consumed.
+pub fn consumed_len<I, O, E, F>(mut parser: F) -> impl FnMut(I) -> IResult<I, (usize, O), E>
+where
+    I: Location,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        let start = input.location();
+        parser.parse(input).map(move |(remaining, output)| {
+            let end = remaining.location();
+            (remaining, (end.saturating_sub(start), output))
+        })
+    }
+}
+
+/// Applies a parser and gets whether the span of the input that it consumed
+/// contains `cursor`.
+///
+/// The span contains the cursor if the cursor is at or between its start and
+/// end, so a cursor that immediately follows a construct is contained by it.
+pub fn contains_cursor<I, O, E, F>(
+    cursor: usize,
+    parser: F,
+) -> impl FnMut(I) -> IResult<I, (bool, Spanned<O>), E>
+where
+    I: Location,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        let (remaining, (span, output)) = parser(input)?;
+        let contains = span.start <= cursor && cursor <= span.end;
+        Ok((remaining, (contains, Spanned::new(span, output))))
+    }
+}
+
+/// Gets the ranges of a source of length `source_len` that are not covered by
+/// any of the given spans.
+///
+/// Spans are clamped to the source, so any portion of a span beyond
+/// `source_len` is ignored. Empty spans cover nothing and the ranges are never
+/// empty.
+pub fn coverage(source_len: usize, spans: &[Range<usize>]) -> Vec<Range<usize>> {
+    let mut spans: Vec<_> = spans.iter().filter(|span| !span.is_empty()).collect();
+    spans.sort_by_key(|span| span.start);
+    let mut gaps = Vec::new();
+    let mut start = 0;
+    for span in spans {
+        let end = span.start.min(source_len);
+        if end > start {
+            gaps.push(start..end);
+        }
+        start = start.max(span.end.min(source_len));
+        if start >= source_len {
+            break;
+        }
+    }
+    if start < source_len {
+        gaps.push(start..source_len);
+    }
+    gaps
+}
+
+/// Commits to a parser, converting its errors into failures with context.
+///
+/// The context is located at the input given to the combinator, which is the
+/// location at which the parse is committed.
+pub fn cut_at<I, O, E, F>(label: &'static str, mut parser: F) -> impl FnMut(I) -> IResult<I, O, E>
+where
+    I: Clone,
+    E: ContextError<I> + ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| match parser.parse(input.clone()) {
+        Err(ErrorMode::Error(error)) | Err(ErrorMode::Failure(error)) => {
+            Err(ErrorMode::Failure(E::add_context(input, label, error)))
+        }
+        result => result,
+    }
+}
+
+/// Renders a tree as indented lines of labels and spans.
+pub fn debug_tree<T>(node: &T) -> String
+where
+    T: DebugTree,
+{
+    fn render(node: &dyn DebugTree, depth: usize, tree: &mut String) {
+        tree.extend(iter::repeat("  ").take(depth));
+        tree.push_str(&node.label());
+        if let Some(span) = node.span() {
+            tree.push_str(&format!(" [{}..{}]", span.start, span.end));
+        }
+        tree.push('\n');
+        for child in node.children() {
+            render(child, depth + 1, tree);
+        }
+    }
+
+    let mut tree = String::new();
+    render(node, 0, &mut tree);
+    tree
+}
+
+/// Parses a decimal literal and gets its integer and fractional parts and
+/// their spans.
+///
+/// The fractional part excludes the decimal point and is `None` if there is
+/// no decimal point. A trailing decimal point is consumed and yields an empty
+/// fractional part.
+#[allow(clippy::type_complexity)]
+pub fn decimal_parts_spanned<I, E>(input: I) -> IResult<I, (Spanned<I>, Option<Spanned<I>>), E>
+where
+    I: Clone + InputIter + InputLength + InputTakeAtPosition + Location + Slice<RangeFrom<usize>>,
+    <I as InputIter>::Item: AsChar,
+    <I as InputTakeAtPosition>::Item: AsChar,
+    E: ParseError<I>,
+{
+    let (input, (span, integer)) = span_range(digit1)(input)?;
+    let integer = Spanned::new(span, integer);
+    match char::<I, E>('.')(input.clone()) {
+        Ok((input, _)) => {
+            let (remaining, (span, fraction)) = span_range(digit0)(input)?;
+            Ok((remaining, (integer, Some(Spanned::new(span, fraction)))))
+        }
+        Err(ErrorMode::Error(_)) => Ok((input, (integer, None))),
+        Err(error) => Err(error),
+    }
+}
+
+/// Parses the name of a symbol and records its span as the definition of the
+/// symbol in the state.
+#[allow(clippy::type_complexity)]
+pub fn define<I, O, E, F>(
+    name: F,
+) -> impl FnMut(Stateful<I, SymbolTable>) -> IResult<Stateful<I, SymbolTable>, O, E>
+where
+    Stateful<I, SymbolTable>: Location,
+    O: AsRef<str>,
+    E: ParseError<Stateful<I, SymbolTable>>,
+    F: Parser<Stateful<I, SymbolTable>, O, E>,
+{
+    let mut name = span_range(name);
+    move |input: Stateful<I, SymbolTable>| {
+        let (mut remaining, (span, output)) = name(input)?;
+        let symbol = remaining
+            .state
+            .symbols
+            .entry(output.as_ref().to_owned())
+            .or_default();
+        if symbol.definition.is_none() {
+            symbol.definition = Some(span);
+        }
+        Ok((remaining, output))
+    }
+}
+
+/// Parses a list of items separated by a separator and gets the spans of the
+/// items and separators.
+///
+/// Like `separated_list0`, a separator that is not followed by an item is not
+/// consumed. For a non-empty list, there is one less separator than items.
+#[allow(clippy::type_complexity)]
+pub fn delimited_list_with_seps<I, O1, O2, E, F, G>(
+    separator: F,
+    item: G,
+) -> impl FnMut(I) -> IResult<I, (Vec<Spanned<O2>>, Vec<Range<usize>>), E>
+where
+    I: Clone + InputLength + Location,
+    E: ParseError<I>,
+    F: Parser<I, O1, E>,
+    G: Parser<I, O2, E>,
+{
+    let mut separator = span_range(separator);
+    let mut item = span_range(item);
+    move |input: I| {
+        let mut items = Vec::new();
+        let mut separators = Vec::new();
+        let mut input = match item(input.clone()) {
+            Ok((remaining, (span, output))) => {
+                items.push(Spanned::new(span, output));
+                remaining
+            }
+            Err(ErrorMode::Error(_)) => return Ok((input, (items, separators))),
+            Err(error) => return Err(error),
+        };
+        loop {
+            let len = input.input_len();
+            let (remaining, (separator_span, _)) = match separator(input.clone()) {
+                Ok(output) => output,
+                Err(ErrorMode::Error(_)) => break,
+                Err(error) => return Err(error),
+            };
+            if remaining.input_len() == len {
+                return Err(ErrorMode::Error(E::from_error_kind(
+                    remaining,
+                    ErrorKind::SeparatedList,
+                )));
+            }
+            match item(remaining) {
+                Ok((remaining, (span, output))) => {
+                    items.push(Spanned::new(span, output));
+                    separators.push(separator_span);
+                    input = remaining;
+                }
+                Err(ErrorMode::Error(_)) => break,
+                Err(error) => return Err(error),
+            }
+        }
+        Ok((input, (items, separators)))
+    }
+}
+
+/// Parses a delimited construct and gets the spans of its opening and closing
+/// delimiters.
+///
+/// When `inner` parses nested constructs via this combinator, each level gets
+/// the spans of its own delimiters.
+#[allow(clippy::type_complexity)]
+pub fn delimiter_pair_spans<I, O1, O2, O3, E, F, G, H>(
+    open: F,
+    mut inner: G,
+    close: H,
+) -> impl FnMut(I) -> IResult<I, (Range<usize>, Range<usize>, O2), E>
+where
+    I: Location,
+    E: ParseError<I>,
+    F: Parser<I, O1, E>,
+    G: Parser<I, O2, E>,
+    H: Parser<I, O3, E>,
+{
+    let mut open = span_range(open);
+    let mut close = span_range(close);
+    move |input: I| {
+        let (input, (opener, _)) = open(input)?;
+        let (input, output) = inner.parse(input)?;
+        let (remaining, (closer, _)) = close(input)?;
+        Ok((remaining, (opener, closer, output)))
+    }
+}
+
+/// Gets the spans and kinds of line endings in `source` that differ from the
+/// dominant line ending.
+///
+/// The dominant line ending is the most common. Ties are broken by the line
+/// ending that occurs first.
+pub fn detect_mixed_line_endings(source: &str) -> Vec<(Range<usize>, LineEnding)> {
+    let bytes = source.as_bytes();
+    let mut endings = Vec::new();
+    let mut n = 0;
+    while n < bytes.len() {
+        match (bytes[n], bytes.get(n + 1)) {
+            (b'\r', Some(b'\n')) => {
+                endings.push((n..(n + 2), LineEnding::CrLf));
+                n += 2;
+            }
+            (b'\r', _) => {
+                endings.push((n..(n + 1), LineEnding::Cr));
+                n += 1;
+            }
+            (b'\n', _) => {
+                endings.push((n..(n + 1), LineEnding::Lf));
+                n += 1;
+            }
+            _ => {
+                n += 1;
+            }
+        }
+    }
+    let mut counts = [0usize; 3];
+    for (_, ending) in endings.iter() {
+        counts[*ending as usize] += 1;
+    }
+    let dominant = endings.iter().map(|(_, ending)| *ending).fold(
+        None,
+        |dominant: Option<LineEnding>, kind| match dominant {
+            Some(dominant) if counts[dominant as usize] >= counts[kind as usize] => Some(dominant),
+            _ => Some(kind),
+        },
+    );
+    endings
+        .into_iter()
+        .filter(|(_, ending)| Some(*ending) != dominant)
+        .collect()
+}
+
+/// Applies a parser and gets the span of the input that it consumed as both a
+/// range of locations and a pair of lines and columns.
+#[allow(clippy::type_complexity)]
+pub fn dual_span<'x, I, O, E, F>(
+    index: &'x LineIndex,
+    parser: F,
+) -> impl FnMut(I) -> IResult<I, (Range<usize>, (LineColumn, LineColumn), O), E> + 'x
+where
+    I: Location + 'x,
+    O: 'x,
+    E: ParseError<I> + 'x,
+    F: Parser<I, O, E> + 'x,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        let (remaining, (span, output)) = parser(input)?;
+        let position = (index.line_col(span.start), index.line_col(span.end));
+        Ok((remaining, (span, position, output)))
+    }
+}
+
+/// Applies a parser and writes the span of the input that it consumed to a
+/// `SpanSink` with the given kind.
+///
+/// Spans are written when the parser succeeds, including when an enclosing
+/// parser later backtracks.
+#[cfg(feature = "serde")]
+pub fn emit_span<'x, I, O, E, F, W>(
+    sink: &'x RefCell<SpanSink<W>>,
+    kind: &'static str,
+    parser: F,
+) -> impl FnMut(I) -> IResult<I, O, E> + 'x
+where
+    I: Location + 'x,
+    O: 'x,
+    E: ParseError<I> + 'x,
+    F: Parser<I, O, E> + 'x,
+    W: Write,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        let (remaining, (span, output)) = parser(input)?;
+        sink.borrow_mut().emit(kind, span);
+        Ok((remaining, output))
+    }
+}
+
+/// Gets the smallest span that contains all of the given offsets, clamped to
+/// a source of length `source_len`.
+///
+/// Returns `None` if there are no offsets.
+pub fn enclosing_span(offsets: &[usize], source_len: usize) -> Option<Range<usize>> {
+    let start = offsets.iter().copied().min()?;
+    let end = offsets.iter().copied().max()?.saturating_add(1);
+    Some(start.min(source_len)..end.min(source_len))
+}
+
+/// Parses a line ending or the end of input.
+///
+/// At the end of input, no input is consumed and the output is empty.
+pub fn eol<I, E>(input: I) -> IResult<I, I, E>
+where
+    I: Clone
+        + Compare<&'static str>
+        + InputIter
+        + InputLength
+        + Slice<Range<usize>>
+        + Slice<RangeFrom<usize>>
+        + Slice<RangeTo<usize>>,
+    E: ParseError<I>,
+{
+    if input.input_len() == 0 {
+        Ok((input.clone(), input))
+    }
+    else {
+        line_ending(input)
+    }
+}
+
+/// Parses and transforms escaped text like `escaped_transform` and gets the
+/// span of the escaped text.
+///
+/// The span covers the source of the entire output, including control
+/// characters and escapes, rather than the transformed output. Use
+/// `escaped_transform_mapped` to get the location of each decoded character.
+pub fn escaped_located<I, O1, O2, X, O, E, F, G>(
+    normal: F,
+    control: char,
+    transform: G,
+) -> impl FnMut(I) -> IResult<I, (Range<usize>, O), E>
+where
+    I: Clone
+        + ExtendInto<Item = X, Extender = O>
+        + InputIter
+        + InputLength
+        + InputTake
+        + InputTakeAtPosition
+        + Location
+        + Offset
+        + Slice<RangeFrom<usize>>,
+    <I as InputIter>::Item: AsChar,
+    O1: ExtendInto<Item = X, Extender = O>,
+    O2: ExtendInto<Item = X, Extender = O>,
+    E: ParseError<I>,
+    F: Parser<I, O1, E>,
+    G: Parser<I, O2, E>,
+{
+    span_range(escaped_transform(normal, control, transform))
+}
+
+/// Parses and transforms escaped text and gets the locations of the decoded
+/// characters.
+///
+/// This behaves like `escaped_transform`, but also gets the location in the
+/// source of each character of the output. Characters produced by `normal`
+/// are located at their source character and characters produced by
+/// `transform` are located at the control character of their escape.
+#[allow(clippy::type_complexity)]
+pub fn escaped_transform_mapped<I, O, E, F, G>(
+    mut normal: F,
+    control: char,
+    mut transform: G,
+) -> impl FnMut(I) -> IResult<I, (String, Vec<usize>), E>
+where
+    I: Clone + InputIter + InputLength + Location + Slice<RangeFrom<usize>>,
+    <I as InputIter>::Item: AsChar,
+    O: AsRef<str>,
+    E: ParseError<I>,
+    F: Parser<I, I, E>,
+    G: Parser<I, O, E>,
+{
+    move |mut input: I| {
+        let mut decoded = String::new();
+        let mut locations = Vec::new();
+        while input.input_len() > 0 {
+            let len = input.input_len();
+            match normal.parse(input.clone()) {
+                Ok((remaining, fragment)) if remaining.input_len() < len => {
+                    let start = fragment.location();
+                    for (n, item) in fragment.iter_indices() {
+                        decoded.push(item.as_char());
+                        locations.push(start + n);
+                    }
+                    input = remaining;
+                    continue;
+                }
+                Ok(_) | Err(ErrorMode::Error(_)) => {}
+                Err(error) => return Err(error),
+            }
+            let is_control = input
+                .iter_elements()
+                .next()
+                .is_some_and(|item| item.as_char() == control);
+            if !is_control {
+                break;
+            }
+            let start = input.location();
+            match transform.parse(input.slice(control.len_utf8()..)) {
+                Ok((remaining, output)) => {
+                    for c in output.as_ref().chars() {
+                        decoded.push(c);
+                        locations.push(start);
+                    }
+                    input = remaining;
+                }
+                Err(ErrorMode::Error(error)) => {
+                    return Err(ErrorMode::Error(E::append(
+                        input,
+                        ErrorKind::EscapedTransform,
+                        error,
+                    )));
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        Ok((input, (decoded, locations)))
+    }
+}
+
+/// Parses exactly `n` items and gets their span.
+///
+/// Items are those of `InputIter`, such as `char`s of `str` input, so the span
+/// may be longer than `n` when items are encoded with more than one byte. When
+/// fewer than `n` items remain, the error spans the remaining input.
+pub fn fixed_width_span<I, E>(n: usize) -> impl Fn(I) -> IResult<I, (Range<usize>, I), E>
+where
+    I: InputIter + InputLength + InputTake + Location,
+    E: SpanError<I>,
+{
+    move |input: I| {
+        let start = input.location();
+        match input.slice_index(n) {
+            Ok(index) => {
+                let (remaining, output) = input.take_split(index);
+                Ok((remaining, (start..(start + index), output)))
+            }
+            Err(_) => {
+                let len = input.input_len();
+                Err(ErrorMode::Error(E::from_span(
+                    input,
+                    start..(start + len),
+                    ErrorKind::Eof,
+                )))
+            }
+        }
+    }
+}
+
+/// Like `fold_many0`, but also gets the span of the input consumed by all
+/// applications of the parser.
+///
+/// When the parser does not match, the span is zero-width at the current
+/// location.
+pub fn fold_many0_span<I, O, R, E, F, G, H>(
+    mut parser: F,
+    mut init: H,
+    mut fold: G,
+) -> impl FnMut(I) -> IResult<I, (Range<usize>, R), E>
+where
+    I: Clone + InputLength + Location,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+    G: FnMut(R, O) -> R,
+    H: FnMut() -> R,
+{
+    move |mut input: I| {
+        let mut accumulator = init();
+        let mut span: Option<Range<usize>> = None;
+        loop {
+            let start = input.location();
+            let len = input.input_len();
+            match parser.parse(input.clone()) {
+                Ok((remaining, output)) => {
+                    // Like `fold_many0`, fail rather than loop forever if the
+                    // parser does not consume input.
+                    if remaining.input_len() == len {
+                        return Err(ErrorMode::Error(E::from_error_kind(
+                            remaining,
+                            ErrorKind::Many0,
+                        )));
+                    }
+                    let end = remaining.location();
+                    span = Some(match span {
+                        Some(span) => merge_spans(span, start..end),
+                        None => start..end,
+                    });
+                    accumulator = fold(accumulator, output);
+                    input = remaining;
+                }
+                Err(ErrorMode::Error(_)) => {
+                    let span = span.unwrap_or(start..start);
+                    return Ok((input, (span, accumulator)));
+                }
+                Err(error) => {
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
+
+/// Parses a delimited block and gets a span of its lines for folding.
+///
+/// The span begins at the start of the line of `open` and ends at the start
+/// of the line of `close`, so the closing line is excluded from the fold and
+/// remains visible.
+#[allow(clippy::type_complexity)]
+pub fn foldable<'x, I, O1, O2, O3, E, F, G, H>(
+    index: &'x LineIndex,
+    mut open: F,
+    mut body: G,
+    mut close: H,
+) -> impl FnMut(I) -> IResult<I, Spanned<O2>, E> + 'x
+where
+    I: Location + 'x,
+    O1: 'x,
+    O2: 'x,
+    O3: 'x,
+    E: ParseError<I> + 'x,
+    F: Parser<I, O1, E> + 'x,
+    G: Parser<I, O2, E> + 'x,
+    H: Parser<I, O3, E> + 'x,
+{
+    move |input: I| {
+        let line_start = |location: usize| {
+            index
+                .line_span(index.line_col(location).line)
+                .map_or(location, |span| span.start)
+        };
+        let start = line_start(input.location());
+        let (input, _) = open.parse(input)?;
+        let (input, output) = body.parse(input)?;
+        let end = line_start(input.location());
+        let (input, _) = close.parse(input)?;
+        Ok((input, Spanned::new(start..end, output)))
+    }
+}
+
+/// Applies the first matching parser in a choice and, when all parsers fail,
+/// gets the error with the furthest location.
+///
+/// See `FurthestError`.
+pub fn furthest_alt<I, O, A>(mut branches: A) -> impl FnMut(I) -> IResult<I, O, FurthestError>
+where
+    I: Clone + Location,
+    A: TaggedAlt<I, O, FurthestError>,
+{
+    move |input: I| {
+        branches
+            .choice(input)
+            .map(|(remaining, (_, output))| (remaining, output))
+    }
+}
+
+/// Applies a parser and, on failure, gets an error that spans the input from
+/// the start of the parser to the furthest location reached in `metrics`.
+///
+/// Locations are only reached by parsers wrapped by `metered`, so wrapping the
+/// tokens of a grammar in `metered` reports how far the parser reached before
+/// failing, even when branches backtrack. Only locations reached during this
+/// application of the parser are considered, so progress from earlier parses
+/// does not extend the span. The error keeps the kind of the error from the
+/// parser.
+pub fn furthest_consumed<'x, I, O, E, F>(
+    metrics: &'x RefCell<Metrics>,
+    mut parser: F,
+) -> impl FnMut(I) -> IResult<I, O, E> + 'x
+where
+    I: Clone + Location + 'x,
+    O: 'x,
+    E: SpanError<I> + 'x,
+    F: Parser<I, O, E> + 'x,
+{
+    move |input: I| {
+        let start = input.location();
+        let outer = metrics.borrow_mut().furthest.take();
+        let result = parser.parse(input.clone());
+        let inner = metrics.borrow().furthest;
+        metrics.borrow_mut().furthest = outer.max(inner);
+        result.map_err(|error| {
+            let end = inner.map_or(start, |furthest| furthest.max(start));
+            error.map(|error| error.respan(input.clone(), start..end))
+        })
+    }
+}
+
+/// Gets the zero-based column of `offset` in `source` measured in extended
+/// grapheme clusters.
+///
+/// When `offset` is within a grapheme cluster, the column is that of the
+/// cluster. This is not the display width of the text, as wide characters are
+/// counted as one column.
+#[cfg(feature = "unicode")]
+pub fn grapheme_column(source: &str, offset: usize) -> usize {
+    let offset = offset.min(source.len());
+    let start = source.as_bytes()[..offset]
+        .iter()
+        .rposition(|byte| *byte == b'\n')
+        .map_or(0, |n| n + 1);
+    let end = source[start..]
+        .find('\n')
+        .map_or(source.len(), |n| start + n);
+    // The carriage return of a CRLF line ending is not part of the line, though
+    // it forms a single grapheme cluster with the line feed.
+    let line = &source[start..end];
+    line.strip_suffix('\r')
+        .unwrap_or(line)
+        .grapheme_indices(true)
+        .take_while(|(n, cluster)| start + n + cluster.len() <= offset)
+        .count()
+}
+
+/// Parses a heredoc and gets the span of its body.
+///
+/// A heredoc begins with `<<` followed by `terminator` and a line ending and
+/// ends with a line that consists only of `terminator`. The body is the lines
+/// between these markers, including their line endings, and must be entirely
+/// consumed by `body`. When there is no terminating line, the error spans the
+/// remaining input following the opening marker. When `body` does not consume
+/// the entire body, the error spans the input that it did not consume.
+pub fn heredoc<I, O, E, F>(
+    terminator: &'static str,
+    mut body: F,
+) -> impl FnMut(I) -> IResult<I, (Range<usize>, O), E>
+where
+    I: Clone
+        + Compare<&'static str>
+        + InputIter
+        + InputLength
+        + InputTake
+        + Location
+        + Slice<Range<usize>>
+        + Slice<RangeFrom<usize>>
+        + Slice<RangeTo<usize>>,
+    <I as InputIter>::Item: AsChar,
+    E: SpanError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        let (input, _) = tag("<<")(input)?;
+        let (input, _) = tag(terminator)(input)?;
+        let (input, _) = line_ending(input)?;
+        let is_terminator = |n: usize| {
+            let line = input.slice(n..);
+            line.compare(terminator) == CompareResult::Ok
+                && line
+                    .slice(terminator.len()..)
+                    .iter_elements()
+                    .next()
+                    .map_or(true, |item| matches!(item.as_char(), '\n' | '\r'))
+        };
+        let start = input.location();
+        let len = input.input_len();
+        let n = iter::once(0)
+            .chain(
+                input
+                    .iter_indices()
+                    .filter_map(|(n, item)| (item.as_char() == '\n').then(|| n + 1)),
+            )
+            .find(|n| is_terminator(*n));
+        match n {
+            Some(n) => {
+                let (remaining, fragment) = input.take_split(n);
+                let (unconsumed, output) = body.parse(fragment)?;
+                let unconsumed_len = unconsumed.input_len();
+                if unconsumed_len > 0 {
+                    let location = unconsumed.location();
+                    return Err(ErrorMode::Error(E::from_span(
+                        unconsumed,
+                        location..(location + unconsumed_len),
+                        ErrorKind::Eof,
+                    )));
+                }
+                let (remaining, _) = remaining.take_split(terminator.len());
+                Ok((remaining, (start..(start + n), output)))
+            }
+            None => Err(ErrorMode::Error(E::from_span(
+                input,
+                start..(start + len),
+                ErrorKind::TakeUntil,
+            ))),
+        }
+    }
+}
+
+/// Applies a parser, discards its output, and gets the span of the input that
+/// it consumed.
+pub fn ignored_span<I, O, E, F>(parser: F) -> impl FnMut(I) -> IResult<I, Range<usize>, E>
+where
+    I: Location,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    let mut parser = span_range(parser);
+    move |input: I| parser(input).map(|(remaining, (span, _))| (remaining, span))
+}
+
+/// Applies a streaming parser and annotates incomplete input with the
+/// location of the frame being parsed.
+///
+/// When the parser needs more input, it fails with an `IncompleteFrame` that
+/// refers to the location of the input given to the combinator. This is the
+/// location from which parsing should resume once more input is available.
+pub fn incomplete_frame<I, O, E, F>(mut parser: F) -> impl FnMut(I) -> IResult<I, O, E>
+where
+    I: Clone + Location,
+    E: FromExternalError<I, IncompleteFrame> + ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        let start = input.location();
+        parser.parse(input.clone()).map_err(|error| match error {
+            ErrorMode::Incomplete(needed) => ErrorMode::Error(E::from_external_error(
+                input,
+                ErrorKind::Complete,
+                IncompleteFrame { start, needed },
+            )),
+            error => error,
+        })
+    }
+}
+
+/// Parses an integer in the given radix with an optional sign.
+///
+/// When the integer is out of range for `T`, the error spans the entire
+/// literal, including any sign. A negative integer is instead rejected with
+/// `ErrorKind::Verify` if `T` is unsigned, and the error spans the sign. Zero
+/// is accepted with either sign.
+///
+/// # Panics
+///
+/// Panics if `radix` is not in the range `2..=36`.
+pub fn integer_spanned<T, I, E>(radix: u32) -> impl Fn(I) -> IResult<I, Spanned<T>, E>
+where
+    T: TryFrom<i128> + TryFrom<u128>,
+    I: InputIter + InputTake + Location,
+    <I as InputIter>::Item: AsChar,
+    E: SpanError<I>,
+{
+    assert!(
+        (2..=36).contains(&radix),
+        "radix must be in the range `2..=36`"
+    );
+    move |input: I| {
+        let mut items = input
+            .iter_indices()
+            .map(|(index, item)| (index, item.as_char()))
+            .peekable();
+        let negative = match items.peek().map(|(_, item)| *item) {
+            Some(sign @ ('+' | '-')) => {
+                items.next();
+                sign == '-'
+            }
+            _ => false,
+        };
+        // The magnitude is accumulated separately from the sign, so that the
+        // full range of both `i128` and `u128` can be parsed.
+        let mut n = 0;
+        let mut magnitude = Some(0u128);
+        for (index, item) in items {
+            let digit = match item.to_digit(radix) {
+                Some(digit) => digit,
+                _ => break,
+            };
+            magnitude = magnitude
+                .and_then(|magnitude| magnitude.checked_mul(radix.into()))
+                .and_then(|magnitude| magnitude.checked_add(digit.into()));
+            n = index + item.len_utf8();
+        }
+        if n == 0 {
+            return Err(ErrorMode::Error(E::from_error_kind(
+                input,
+                ErrorKind::Digit,
+            )));
+        }
+        let start = input.location();
+        let span = start..(start + n);
+        let (remaining, literal) = input.take_split(n);
+        if negative && magnitude != Some(0) && T::try_from(-1i128).is_err() {
+            return Err(ErrorMode::Error(E::from_span(
+                literal,
+                start..(start + 1),
+                ErrorKind::Verify,
+            )));
+        }
+        let value = magnitude.and_then(|magnitude| {
+            if negative {
+                if magnitude == i128::MIN.unsigned_abs() {
+                    Some(i128::MIN)
+                }
+                else {
+                    i128::try_from(magnitude).ok().map(|value| -value)
+                }
+                .and_then(|value| T::try_from(value).ok())
+            }
+            else {
+                T::try_from(magnitude).ok()
+            }
+        });
+        match value {
+            Some(value) => Ok((remaining, Spanned::new(span, value))),
+            None => Err(ErrorMode::Error(E::from_span(
+                literal,
+                span,
+                ErrorKind::TooLarge,
+            ))),
+        }
+    }
+}
+
+/// Applies a parser and gets its span, the fragment that it matched, and its
+/// output.
+///
+/// The fragment is suitable as a key for interning source text alongside its
+/// span.
+#[allow(clippy::type_complexity)]
+pub fn interned<I, O, E, F>(mut parser: F) -> impl FnMut(I) -> IResult<I, (Range<usize>, I, O), E>
+where
+    I: Clone + Location + Offset + Slice<RangeTo<usize>>,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        let start = input.location();
+        let (remaining, output) = parser.parse(input.clone())?;
+        let fragment = input.slice(..input.offset(&remaining));
+        let end = remaining.location();
+        Ok((remaining, (start..end, fragment, output)))
+    }
+}
+
+/// Parses a fragment that is interpolated within an outer input, such as an
+/// expression within a string literal.
+///
+/// The fragment is located by its position in `outer`, so the locations seen
+/// by `parser` and the output span are in the coordinates of the outer input
+/// even when the fragment has been extracted from it without location, such
+/// as by splitting a string literal at its delimiters. When `fragment` is not
+/// within `outer`, the error is located at `outer` with `ErrorKind::Verify`.
+pub fn interpolated<'i, I, O, E, F>(
+    outer: &Located<'i, I>,
+    fragment: &'i I,
+    mut parser: F,
+) -> IResult<Located<'i, I>, Spanned<O>, E>
+where
+    I: ?Sized,
+    &'i I: AsBytes,
+    E: ParseError<Located<'i, I>>,
+    F: Parser<Located<'i, I>, O, E>,
+{
+    match outer.try_slice_to(fragment) {
+        Some(fragment) => {
+            let start = fragment.location();
+            parser.parse(fragment).map(|(remaining, output)| {
+                let end = remaining.location();
+                (remaining, Spanned::new(start..end, output))
+            })
+        }
+        None => Err(ErrorMode::Error(E::from_error_kind(
+            *outer,
+            ErrorKind::Verify,
+        ))),
+    }
+}
+
+/// Parses a key and a value and records the entry in the state.
+///
+/// Entries are recorded in `KeyValues`. When a key has already been recorded,
+/// the entry is not recorded and the spans of both keys are recorded as a
+/// duplicate instead, so parsing continues.
+#[allow(clippy::type_complexity)]
+pub fn kv_map<I, K, V, E, F, G>(
+    key: F,
+    mut value: G,
+) -> impl FnMut(Stateful<I, KeyValues<K, V>>) -> IResult<Stateful<I, KeyValues<K, V>>, (), E>
+where
+    Stateful<I, KeyValues<K, V>>: Location,
+    K: Clone + Eq + Hash,
+    E: ParseError<Stateful<I, KeyValues<K, V>>>,
+    F: Parser<Stateful<I, KeyValues<K, V>>, K, E>,
+    G: Parser<Stateful<I, KeyValues<K, V>>, V, E>,
+{
+    let mut key = span_range(key);
+    move |input: Stateful<I, KeyValues<K, V>>| {
+        let (remaining, (key_span, key)) = key(input)?;
+        let start = remaining.location();
+        let (mut remaining, output) = value.parse(remaining)?;
+        let value_span = start..remaining.location();
+        let state = &mut remaining.state;
+        match state.keys.get(&key) {
+            Some(original) => {
+                let original = original.clone();
+                state.duplicates.push(DuplicateEntry {
+                    original,
+                    duplicate: key_span,
+                });
+            }
+            None => {
+                state.keys.insert(key.clone(), key_span);
+                state.entries.insert(key, Spanned::new(value_span, output));
+            }
+        }
+        Ok((remaining, ()))
+    }
+}
+
+/// Lexes all tokens with a `logos` lexer and gets their spans.
+///
+/// The tokens can be parsed as `LocatedTokens`, which are located by the
+/// spans of the tokens in the source of the lexer. Fails with the span of the
+/// first error.
+#[cfg(feature = "logos")]
+pub fn lex_spanned<'s, T>(lexer: logos::Lexer<'s, T>) -> Result<Vec<Spanned<T>>, Spanned<T::Error>>
+where
+    T: logos::Logos<'s>,
+{
+    lexer
+        .spanned()
+        .map(|(token, span)| match token {
+            Ok(token) => Ok(Spanned::new(span, token)),
+            Err(error) => Err(Spanned::new(span, error)),
+        })
+        .collect()
+}
+
+/// Gets the span and indentation of the line at the start of which the input
+/// is located without consuming any input.
+///
+/// The span includes the line terminator (see `LineIndex::line_span`). The
+/// indentation is measured in columns of leading spaces and tabs, where tabs
+/// advance to the next multiple of `tab_width`. Fails if the input is not
+/// located at the start of a line.
+pub fn line_header<'x, I, E>(
+    index: &'x LineIndex,
+    tab_width: usize,
+) -> impl Fn(I) -> IResult<I, (Range<usize>, usize), E> + 'x
+where
+    I: InputIter + Location + 'x,
+    <I as InputIter>::Item: AsChar,
+    E: ParseError<I> + 'x,
+{
+    move |input: I| {
+        let LineColumn { line, column } = index.line_col(input.location());
+        let span = match index.line_span(line) {
+            Some(span) if column == 0 => span,
+            _ => {
+                return Err(ErrorMode::Error(E::from_error_kind(
+                    input,
+                    ErrorKind::Verify,
+                )))
+            }
+        };
+        let mut indent = 0;
+        for item in input.iter_elements().map(AsChar::as_char) {
+            match item {
+                ' ' => indent += 1,
+                '\t' if tab_width > 0 => indent += tab_width - (indent % tab_width),
+                _ => break,
+            }
+        }
+        Ok((input, (span, indent)))
+    }
+}
+
+/// Parses lines until the end of input and gets the span of each line.
+///
+/// Each line is parsed by `line` and must be followed by a line ending or the
+/// end of input (see `eol`). Spans exclude line terminators, so blank lines
+/// have zero-width spans.
+pub fn lines_of<I, O, E, F>(line: F) -> impl FnMut(I) -> IResult<I, Vec<Spanned<O>>, E>
+where
+    I: Clone
+        + Compare<&'static str>
+        + InputIter
+        + InputLength
+        + Location
+        + Slice<Range<usize>>
+        + Slice<RangeFrom<usize>>
+        + Slice<RangeTo<usize>>,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    let mut line = span_range(line);
+    move |mut input: I| {
+        let mut lines = Vec::new();
+        while input.input_len() > 0 {
+            let (remaining, (span, output)) = line(input)?;
+            let (remaining, _) = eol(remaining)?;
+            lines.push(Spanned::new(span, output));
+            input = remaining;
+        }
+        Ok((input, lines))
+    }
+}
+
+/// Runs a parser over raw input wrapped in `Located` and gets the span of its
+/// output.
+///
+/// The input is located at zero and the remaining input is unwrapped, so
+/// parsers that are generic over their input, such as those in `nom`, can
+/// yield spans without changing the type of input at the entry of a parse.
+/// Errors are those of the parser and so refer to `Located` input.
+#[allow(clippy::type_complexity)]
+pub fn locate<'i, I, O, E, F>(
+    mut parser: F,
+) -> impl FnMut(&'i I) -> IResult<&'i I, (Range<usize>, O), E>
+where
+    I: 'i + ?Sized,
+    E: ParseError<Located<'i, I>>,
+    F: Parser<Located<'i, I>, O, E>,
+{
+    move |input: &'i I| {
+        let (remaining, output) = parser.parse(Located::from(input))?;
+        let end = remaining.location();
+        Ok((remaining.into_data(), (0..end, output)))
+    }
+}
+
+/// Gets the lines of `source` and their spans, excluding line terminators.
+///
+/// Like `str::lines`, there is no empty line following a final line
+/// terminator.
+pub fn located_lines(source: &str) -> impl Iterator<Item = (Range<usize>, &str)> {
+    let mut start = 0;
+    source.split_inclusive('\n').map(move |line| {
+        let text = line
+            .strip_suffix('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .unwrap_or(line);
+        let span = start..(start + text.len());
+        start += line.len();
+        (span, text)
+    })
+}
+
+/// Gets the location of the first element of input that matches a predicate
+/// without consuming any input.
+pub fn located_position<I, E, P>(predicate: P) -> impl Fn(I) -> IResult<I, Option<usize>, E>
+where
+    I: InputIter + Location,
+    E: ParseError<I>,
+    P: Fn(<I as InputIter>::Item) -> bool,
+{
+    move |input: I| {
+        let location = input.position(&predicate).map(|n| input.location() + n);
+        Ok((input, location))
+    }
+}
+
+/// Gets the location of the last element of input that matches a predicate
+/// without consuming any input.
+pub fn located_rposition<I, E, P>(predicate: P) -> impl Fn(I) -> IResult<I, Option<usize>, E>
+where
+    I: InputIter + Location,
+    E: ParseError<I>,
+    P: Fn(<I as InputIter>::Item) -> bool,
+{
+    move |input: I| {
+        let location = input
+            .iter_indices()
+            .fold(None, |last, (n, item)| {
+                if predicate(item) {
+                    Some(n)
+                }
+                else {
+                    last
+                }
+            })
+            .map(|n| input.location() + n);
+        Ok((input, location))
+    }
+}
+
+/// Applies a parser one or more times and requires that each application
+/// consumes input.
+///
+/// When an application of the parser succeeds without advancing the
+/// location, the parser fails with an error that has a zero-width span at
+/// that location rather than looping forever.
+pub fn many1_progress<I, O, E, F>(mut parser: F) -> impl FnMut(I) -> IResult<I, Vec<O>, E>
+where
+    I: Clone + Location,
+    E: SpanError<I>,
+    F: Parser<I, O, E>,
+{
+    move |mut input: I| {
+        let mut items = Vec::new();
+        loop {
+            let start = input.location();
+            match parser.parse(input.clone()) {
+                Ok((remaining, output)) => {
+                    if remaining.location() <= start {
+                        return Err(ErrorMode::Error(E::from_span(
+                            remaining,
+                            start..start,
+                            ErrorKind::Many1,
+                        )));
+                    }
+                    items.push(output);
+                    input = remaining;
+                }
+                Err(ErrorMode::Error(error)) => {
+                    return if items.is_empty() {
+                        Err(ErrorMode::Error(E::append(input, ErrorKind::Many1, error)))
+                    }
+                    else {
+                        Ok((input, items))
+                    };
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// Applies a parser at least `min` and at most `max` times.
+///
+/// Unlike `many_m_n`, this fails rather than stopping when the parser matches
+/// more than `max` times and the error is located where the first excess
+/// match begins.
+pub fn many_bounded<I, O, E, F>(
+    min: usize,
+    max: usize,
+    mut parser: F,
+) -> impl FnMut(I) -> IResult<I, Vec<O>, E>
+where
+    I: Clone + InputLength,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    move |mut input: I| {
+        let mut outputs = Vec::new();
+        loop {
+            let len = input.input_len();
+            match parser.parse(input.clone()) {
+                Ok((remaining, output)) => {
+                    if remaining.input_len() == len {
+                        return Err(ErrorMode::Error(E::from_error_kind(
+                            remaining,
+                            ErrorKind::ManyMN,
+                        )));
+                    }
+                    if outputs.len() == max {
+                        return Err(ErrorMode::Error(E::from_error_kind(
+                            input,
+                            ErrorKind::TooLarge,
+                        )));
+                    }
+                    outputs.push(output);
+                    input = remaining;
+                }
+                Err(ErrorMode::Error(error)) => {
+                    if outputs.len() < min {
+                        return Err(ErrorMode::Error(E::append(input, ErrorKind::ManyMN, error)));
+                    }
+                    return Ok((input, outputs));
+                }
+                Err(error) => {
+                    return Err(error);
+                }
+            }
+        }
+    }
+}
+
+/// Maps the output of a parser with an optional function.
+///
+/// Unlike `map_opt`, the input of errors is only the fragment matched by the
+/// parser, so the location and length of the input span the rejected output.
+pub fn map_opt_located<I, O1, O2, E, F, G>(
+    mut parser: F,
+    mut f: G,
+) -> impl FnMut(I) -> IResult<I, O2, E>
+where
+    I: Clone + Offset + Slice<RangeTo<usize>>,
+    E: ParseError<I>,
+    F: Parser<I, O1, E>,
+    G: FnMut(O1) -> Option<O2>,
+{
+    move |input: I| {
+        let (remaining, output) = parser.parse(input.clone())?;
+        match f(output) {
+            Some(output) => Ok((remaining, output)),
+            None => {
+                let n = input.offset(&remaining);
+                Err(ErrorMode::Error(E::from_error_kind(
+                    input.slice(..n),
+                    ErrorKind::MapOpt,
+                )))
+            }
+        }
+    }
+}
+
+/// Maps the output of a parser with a fallible function.
+///
+/// Unlike `map_res`, the input of errors is only the fragment matched by the
+/// parser, so the location and length of the input span the rejected output.
+pub fn map_res_located<I, O1, O2, X, E, F, G>(
+    mut parser: F,
+    mut f: G,
+) -> impl FnMut(I) -> IResult<I, O2, E>
+where
+    I: Clone + Offset + Slice<RangeTo<usize>>,
+    E: FromExternalError<I, X> + ParseError<I>,
+    F: Parser<I, O1, E>,
+    G: FnMut(O1) -> Result<O2, X>,
+{
+    move |input: I| {
+        let (remaining, output) = parser.parse(input.clone())?;
+        match f(output) {
+            Ok(output) => Ok((remaining, output)),
+            Err(error) => {
+                let n = input.offset(&remaining);
+                Err(ErrorMode::Error(E::from_external_error(
+                    input.slice(..n),
+                    ErrorKind::MapRes,
+                    error,
+                )))
+            }
+        }
+    }
+}
+
+/// Fails if a parser consumes more than `n` bytes of input.
+///
+/// The parser is applied to input that is truncated after the item that
+/// contains the byte at `n`, so the work done by the parser is bounded by the
+/// limit. When the parser consumes more than `n` bytes or needs more input
+/// than it is given, the error spans the input beyond the limit that the
+/// parser consumed or was given.
+pub fn max_consumed<I, O, E, F>(n: usize, mut parser: F) -> impl FnMut(I) -> IResult<I, O, E>
+where
+    I: Clone + InputIter + InputLength + Location + Slice<RangeFrom<usize>> + Slice<RangeTo<usize>>,
+    E: SpanError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        let len = input.input_len();
+        if len <= n {
+            return parser.parse(input);
+        }
+        let end = input
+            .iter_indices()
+            .map(|(index, _)| index)
+            .find(|index| *index > n)
+            .unwrap_or(len);
+        let location = input.location();
+        let error = |input: I, consumed: usize| {
+            ErrorMode::Error(E::from_span(
+                input,
+                (location + n)..(location + consumed),
+                ErrorKind::TooLarge,
+            ))
+        };
+        match parser.parse(input.slice(..end)) {
+            Ok((remaining, output)) => {
+                let consumed = offset_between(&input, &remaining);
+                if consumed > n {
+                    Err(error(input, consumed))
+                }
+                else {
+                    Ok((input.slice(consumed..), output))
+                }
+            }
+            Err(ErrorMode::Incomplete(_)) => Err(error(input, end)),
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// Gets the smallest span that contains both of the given spans.
+pub fn merge_spans(left: Range<usize>, right: Range<usize>) -> Range<usize> {
+    left.start.min(right.start)..left.end.max(right.end)
+}
+
+/// Records a visit to the location of the input in `metrics` each time a
+/// parser is applied and the location of the remaining input each time it
+/// succeeds.
+pub fn metered<'x, I, O, E, F>(
+    metrics: &'x RefCell<Metrics>,
+    mut parser: F,
+) -> impl FnMut(I) -> IResult<I, O, E> + 'x
+where
+    I: Location + 'x,
+    O: 'x,
+    E: ParseError<I> + 'x,
+    F: Parser<I, O, E> + 'x,
+{
+    move |input: I| {
+        metrics.borrow_mut().visit(input.location());
+        let (remaining, output) = parser.parse(input)?;
+        metrics.borrow_mut().reach(remaining.location());
+        Ok((remaining, output))
+    }
+}
+
+/// Gets a zero-width span at the current location for a missing token without
+/// consuming any input.
+pub fn missing_token_span<I, E>(
+    label: &'static str,
+) -> impl Fn(I) -> IResult<I, Spanned<Missing>, E>
+where
+    I: Location,
+    E: ParseError<I>,
+{
+    move |input: I| {
+        let location = input.location();
+        Ok((input, Spanned::new(location..location, Missing { label })))
+    }
+}
+
+/// Updates the line and column in the state of the input from the input
+/// consumed by a parser.
+///
+/// Lines and columns are zero-based and columns are measured in items. Only
+/// input consumed by parsers wrapped in this combinator is counted, so the
+/// state is only accurate if all input preceding a location is parsed this
+/// way.
+pub fn newline_aware<I, O, E, F>(
+    mut parser: F,
+) -> impl FnMut(Stateful<I, LineColumn>) -> IResult<Stateful<I, LineColumn>, O, E>
+where
+    I: Clone + InputIter + Offset + Slice<RangeTo<usize>>,
+    <I as InputIter>::Item: AsChar,
+    F: Parser<Stateful<I, LineColumn>, O, E>,
+{
+    move |input: Stateful<I, LineColumn>| {
+        let mut position = input.state;
+        let (mut remaining, output) = parser.parse(input.clone())?;
+        let consumed = input.data.slice(..input.data.offset(&remaining.data));
+        for item in consumed.iter_elements() {
+            if item.as_char() == '\n' {
+                position.line += 1;
+                position.column = 0;
+            }
+            else {
+                position.column += 1;
+            }
+        }
+        remaining.state = position;
+        Ok((remaining, output))
+    }
+}
+
+/// Applies a parser and gives the span of the input that it consumed to an
+/// observer with the given kind.
+///
+/// The observer receives spans as they are parsed, including when an
+/// enclosing parser later backtracks, so spans need not be collected.
+pub fn observe<'x, I, O, E, F, S>(
+    observer: &'x RefCell<S>,
+    kind: &'static str,
+    parser: F,
+) -> impl FnMut(I) -> IResult<I, O, E> + 'x
+where
+    I: Location + 'x,
+    O: 'x,
+    E: ParseError<I> + 'x,
+    F: Parser<I, O, E> + 'x,
+    S: SpanObserver,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        let (remaining, (span, output)) = parser(input)?;
+        observer.borrow_mut().on_node(kind, span);
+        Ok((remaining, output))
+    }
+}
+
+/// Gets the distance between the locations of two inputs.
+///
+/// This assumes that `later` follows `earlier` and is zero otherwise.
+pub fn offset_between<I>(earlier: &I, later: &I) -> usize
+where
+    I: Location,
+{
+    later.location().saturating_sub(earlier.location())
+}
+
+/// Succeeds without consuming input if the current column is the same as the
+/// reference column in the state.
+///
+/// See [`with_reference_column`].
+pub fn offside_eq<I, E>(input: Stateful<I, usize>) -> IResult<Stateful<I, usize>, (), E>
+where
+    I: FullLocation,
+    E: ParseError<Stateful<I, usize>>,
+{
+    let (_, _, column) = input.full_location();
+    if column == input.state {
+        Ok((input, ()))
+    }
+    else {
+        Err(ErrorMode::Error(E::from_error_kind(
+            input,
+            ErrorKind::Verify,
+        )))
+    }
+}
+
+/// Succeeds without consuming input if the current column is greater than the
+/// reference column in the state.
+///
+/// See [`with_reference_column`].
+pub fn offside_gt<I, E>(input: Stateful<I, usize>) -> IResult<Stateful<I, usize>, (), E>
+where
+    I: FullLocation,
+    E: ParseError<Stateful<I, usize>>,
+{
+    let (_, _, column) = input.full_location();
+    if column > input.state {
+        Ok((input, ()))
+    }
+    else {
+        Err(ErrorMode::Error(E::from_error_kind(
+            input,
+            ErrorKind::Verify,
+        )))
+    }
+}
+
+/// Parses a delimited construct and gets the spans of both its opening token
+/// and the entire construct.
+///
+/// The span of the construct covers the opening token through the closing
+/// token. The output is that of the inner parser.
+#[allow(clippy::type_complexity)]
+pub fn opener_and_full<I, O1, O2, O3, E, F, G, H>(
+    open: F,
+    mut inner: G,
+    mut close: H,
+) -> impl FnMut(I) -> IResult<I, (Range<usize>, Range<usize>, O2), E>
+where
+    I: Location,
+    E: ParseError<I>,
+    F: Parser<I, O1, E>,
+    G: Parser<I, O2, E>,
+    H: Parser<I, O3, E>,
+{
+    let mut open = span_range(open);
+    move |input: I| {
+        let start = input.location();
+        let (input, (opener, _)) = open(input)?;
+        let (input, output) = inner.parse(input)?;
+        let (remaining, _) = close.parse(input)?;
+        let end = remaining.location();
+        Ok((remaining, (opener, start..end, output)))
+    }
+}
+
+/// Gets the smallest span that contains the spans of all of the given
+/// children.
+///
+/// When there are no children, the span is empty and located at `fallback`.
+pub fn parent_span<T>(children: &[Spanned<T>], fallback: usize) -> Range<usize> {
+    children
+        .iter()
+        .map(|child| child.span.clone())
+        .reduce(merge_spans)
+        .unwrap_or(fallback..fallback)
+}
+
+/// Applies a parser without consuming input and gets the span that it would
+/// have consumed.
+pub fn peek_span<I, O, E, F>(parser: F) -> impl FnMut(I) -> IResult<I, (Range<usize>, O), E>
+where
+    I: Clone + Location,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        let (_, output) = parser(input.clone())?;
+        Ok((input, output))
+    }
+}
+
+/// Applies a set of parsers in any order and gets their outputs and the span
+/// of each output.
+///
+/// Outputs are in the order of the parsers, but spans are in the order in
+/// which the parsers matched the input.
+#[allow(clippy::type_complexity)]
+pub fn permutation_spanned<I, O, E, T>(
+    mut parsers: T,
+) -> impl FnMut(I) -> IResult<I, (O, Vec<Range<usize>>), E>
+where
+    I: Clone + Location,
+    E: ParseError<I>,
+    T: SpannedPermutation<I, O, E>,
+{
+    move |input: I| parsers.permute_spanned(input)
+}
+
+/// Pops an item from the stack in the state.
+///
+/// The stack is a `Vec` in which the last item is the top, as pushed by `push`.
+/// Fails with `ErrorKind::Verify` at the current input if the stack is empty.
+pub fn pop<I, T, E>(mut input: Stateful<I, Vec<T>>) -> IResult<Stateful<I, Vec<T>>, T, E>
+where
+    E: ParseError<Stateful<I, Vec<T>>>,
+{
+    match input.state.pop() {
+        Some(item) => Ok((input, item)),
+        None => Err(ErrorMode::Error(E::from_error_kind(
+            input,
+            ErrorKind::Verify,
+        ))),
+    }
+}
+
+/// Invokes a callback each time a parser consumes past a multiple of `step`
+/// bytes.
+///
+/// The callback receives the location of each multiple. The callback is
+/// invoked after the parser succeeds, so this should wrap parsers that are
+/// applied repeatedly, such as the items of a repetition.
+pub fn progress<I, O, E, F, G>(
+    step: usize,
+    mut callback: G,
+    mut parser: F,
+) -> impl FnMut(I) -> IResult<I, O, E>
+where
+    I: Location,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+    G: FnMut(usize),
+{
+    let step = step.max(1);
+    move |input: I| {
+        let start = input.location();
+        let (remaining, output) = parser.parse(input)?;
+        let end = remaining.location();
+        for n in (start / step + 1)..=(end / step) {
+            callback(n * step);
+        }
+        Ok((remaining, output))
+    }
+}
+
+/// Applies a parser and pushes its output onto the stack in the state.
+///
+/// The output is pushed when the parser succeeds and is also returned, so it can
+/// later be popped by `pop`. The stack is only modified in the remaining input, so
+/// backtracking observes the prior stack.
+#[allow(clippy::type_complexity)]
+pub fn push<I, T, E, F>(
+    mut parser: F,
+) -> impl FnMut(Stateful<I, Vec<T>>) -> IResult<Stateful<I, Vec<T>>, T, E>
+where
+    T: Clone,
+    E: ParseError<Stateful<I, Vec<T>>>,
+    F: Parser<Stateful<I, Vec<T>>, T, E>,
+{
+    move |input: Stateful<I, Vec<T>>| {
+        parser.parse(input).map(|(mut remaining, output)| {
+            remaining.state.push(output.clone());
+            (remaining, output)
+        })
+    }
+}
+
+/// Parses a delimited construct and gets the output of `inner` with a span that
+/// includes the delimiters.
+pub fn quoted_spanned<I, O1, O2, O3, E, F, G, H>(
+    mut open: F,
+    mut inner: G,
+    mut close: H,
+) -> impl FnMut(I) -> IResult<I, Spanned<O2>, E>
+where
+    I: Location,
+    E: ParseError<I>,
+    F: Parser<I, O1, E>,
+    G: Parser<I, O2, E>,
+    H: Parser<I, O3, E>,
+{
+    move |input: I| {
+        let start = input.location();
+        let (input, _) = open.parse(input)?;
+        let (input, value) = inner.parse(input)?;
+        let (input, _) = close.parse(input)?;
+        let end = input.location();
+        Ok((input, Spanned::new(start..end, value)))
+    }
+}
+
+/// Applies a parser and records the span of the input that it consumed as a
+/// node of the given kind in the state.
+#[allow(clippy::type_complexity)]
+pub fn record_node<I, O, E, F>(
+    kind: &'static str,
+    parser: F,
+) -> impl FnMut(Stateful<I, SpanReport>) -> IResult<Stateful<I, SpanReport>, O, E>
+where
+    Stateful<I, SpanReport>: Location,
+    E: ParseError<Stateful<I, SpanReport>>,
+    F: Parser<Stateful<I, SpanReport>, O, E>,
+{
+    let mut parser = span_range(parser);
+    move |input: Stateful<I, SpanReport>| {
+        let (mut remaining, (span, output)) = parser(input)?;
+        remaining.state.record(kind, span);
+        Ok((remaining, output))
+    }
+}
+
+/// Applies a parser and records the span of the input that it consumed in
+/// the state.
+#[allow(clippy::type_complexity)]
+pub fn record_span<I, O, E, F>(
+    key: &'static str,
+    parser: F,
+) -> impl FnMut(Stateful<I, SpanCollector>) -> IResult<Stateful<I, SpanCollector>, O, E>
+where
+    Stateful<I, SpanCollector>: Location,
+    E: ParseError<Stateful<I, SpanCollector>>,
+    F: Parser<Stateful<I, SpanCollector>, O, E>,
+{
+    let mut parser = span_range(parser);
+    move |input: Stateful<I, SpanCollector>| {
+        let (mut remaining, (span, output)) = parser(input)?;
+        remaining.state.record(key, span);
+        Ok((remaining, output))
+    }
+}
+
+/// Applies parsers in sequence and records their spans by key.
+///
+/// Each `key => parser` pair is applied via `record_span`, where the key is
+/// the stringified identifier. The output is a tuple of the parser outputs.
+#[macro_export]
+macro_rules! record {
+    ($($key:ident => $parser:expr),+$(,)?) => {
+        $crate::nom::sequence::tuple(($($crate::record_span(stringify!($key), $parser),)+))
+    };
+}
+
+/// Applies a parser and, on error, recovers by skipping to the next
+/// synchronization item.
+///
+/// The synchronization set is given by `sync`, which determines whether an
+/// item is a synchronization token, such as `;` or `}`. When the parser
+/// fails with an error, input is skipped up to but excluding the first such
+/// item (or to the end of input) and the output is the span of the skipped
+/// input. Failures are not recovered.
+#[allow(clippy::type_complexity)]
+pub fn recover_to_sync<I, O, E, F, P>(
+    mut parser: F,
+    sync: P,
+) -> impl FnMut(I) -> IResult<I, Result<O, Range<usize>>, E>
+where
+    I: Clone + InputIter + InputLength + InputTake + Location,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+    P: Fn(<I as InputIter>::Item) -> bool,
+{
+    move |input: I| match parser.parse(input.clone()) {
+        Ok((remaining, output)) => Ok((remaining, Ok(output))),
+        Err(ErrorMode::Error(_)) => {
+            let start = input.location();
+            let n = input.position(&sync).unwrap_or_else(|| input.input_len());
+            let (remaining, _) = input.take_split(n);
+            let end = remaining.location();
+            Ok((remaining, Err(start..end)))
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Applies a parser and fails if the rule is re-entered at the same location
+/// without consuming input.
+///
+/// The rules and locations that are being parsed are recorded in the state.
+/// When a rule is re-entered at a location before it has consumed any input
+/// from that location, such as by left recursion, the parser fails with a
+/// `LeftRecursion` failure rather than looping forever.
+#[allow(clippy::type_complexity)]
+pub fn recursion_guard<I, O, E, F>(
+    rule: &'static str,
+    mut parser: F,
+) -> impl FnMut(
+    Stateful<I, Vec<(&'static str, usize)>>,
+) -> IResult<Stateful<I, Vec<(&'static str, usize)>>, O, E>
+where
+    Stateful<I, Vec<(&'static str, usize)>>: Location,
+    E: FromExternalError<Stateful<I, Vec<(&'static str, usize)>>, LeftRecursion>
+        + ParseError<Stateful<I, Vec<(&'static str, usize)>>>,
+    F: Parser<Stateful<I, Vec<(&'static str, usize)>>, O, E>,
+{
+    move |mut input: Stateful<I, Vec<(&'static str, usize)>>| {
+        let location = input.location();
+        let entry = (rule, location);
+        if input.state.contains(&entry) {
+            return Err(ErrorMode::Failure(E::from_external_error(
+                input,
+                ErrorKind::Verify,
+                LeftRecursion { rule, location },
+            )));
+        }
+        input.state.push(entry);
+        parser.parse(input).map(|(mut remaining, output)| {
+            if let Some(n) = remaining.state.iter().rposition(|active| *active == entry) {
+                remaining.state.remove(n);
+            }
+            (remaining, output)
+        })
+    }
+}
+
+/// Parses the name of a symbol and records its span as a reference to the
+/// symbol in the state.
+#[allow(clippy::type_complexity)]
+pub fn reference<I, O, E, F>(
+    name: F,
+) -> impl FnMut(Stateful<I, SymbolTable>) -> IResult<Stateful<I, SymbolTable>, O, E>
+where
+    Stateful<I, SymbolTable>: Location,
+    O: AsRef<str>,
+    E: ParseError<Stateful<I, SymbolTable>>,
+    F: Parser<Stateful<I, SymbolTable>, O, E>,
+{
+    let mut name = span_range(name);
+    move |input: Stateful<I, SymbolTable>| {
+        let (mut remaining, (span, output)) = name(input)?;
+        remaining
+            .state
+            .symbols
+            .entry(output.as_ref().to_owned())
+            .or_default()
+            .references
+            .push(span);
+        Ok((remaining, output))
+    }
+}
+
+/// Applies a parser and gets the span of the input that it consumed relative
+/// to `parent_start`.
+///
+/// Locations that precede `parent_start` are clamped to zero.
+pub fn relative_to<I, O, E, F>(
+    parent_start: usize,
+    parser: F,
+) -> impl FnMut(I) -> IResult<I, (Range<usize>, O), E>
+where
+    I: Location,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        parser(input).map(|(remaining, (span, output))| {
+            let start = span.start.saturating_sub(parent_start);
+            let end = span.end.saturating_sub(parent_start);
+            (remaining, (start..end, output))
+        })
+    }
+}
+
+/// Renders the lines of `source` that intersect `span` with a caret
+/// underline beneath the spanned text of each line.
+///
+/// Tabs that precede the span in a line are preserved in the underline so that
+/// carets align with the text regardless of tab width. Endpoints of `span`
+/// that are not on a `char` boundary are moved to the preceding boundary.
+pub fn render_excerpt(source: &str, span: Range<usize>) -> String {
+    let boundary = |offset: usize| {
+        let mut offset = offset.min(source.len());
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    };
+    let span = boundary(span.start)..boundary(span.end);
+    let mut excerpt = String::new();
+    let mut start = 0;
+    for line in source.split('\n') {
+        let end = start + line.len();
+        let text = line.strip_suffix('\r').unwrap_or(line);
+        let is_touched = if span.is_empty() {
+            (start..=end).contains(&span.start)
+        }
+        else {
+            span.start <= end && span.end > start
+        };
+        if is_touched {
+            let len = text.len();
+            let lo = (span.start.max(start) - start).min(len);
+            let hi = (span.end.min(start + len).saturating_sub(start)).max(lo);
+            let (prefix, marked) = (&text[..lo], &text[lo..hi]);
+            excerpt.push_str(text);
+            excerpt.push('\n');
+            excerpt.extend(prefix.chars().map(|c| {
+                if c == '\t' {
+                    '\t'
+                }
+                else {
+                    ' '
+                }
+            }));
+            excerpt.extend(iter::repeat('^').take(marked.chars().count().max(1)));
+            excerpt.push('\n');
+        }
+        start = end + 1;
+    }
+    excerpt
+}
+
+/// Applies a parser and emits a diagnostic with the span of the input that it
+/// consumed.
+///
+/// Diagnostics are emitted when the parser succeeds, including when an
+/// enclosing parser later backtracks. The output of the parser is unchanged.
+///
+/// When the parser fails with an error or failure, a diagnostic is emitted
+/// with an empty span at the location of the input given to the parser and
+/// the error is returned unchanged. Incomplete input is not reported.
+pub fn report<'x, I, O, E, F, D>(
+    diagnostics: &'x RefCell<D>,
+    severity: Severity,
+    message: &'x str,
+    parser: F,
+) -> impl FnMut(I) -> IResult<I, O, E> + 'x
+where
+    I: Location + 'x,
+    O: 'x,
+    E: ParseError<I> + 'x,
+    F: Parser<I, O, E> + 'x,
+    D: Diagnostics,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        let start = input.location();
+        match parser(input) {
+            Ok((remaining, (span, output))) => {
+                diagnostics.borrow_mut().emit(span, severity, message);
+                Ok((remaining, output))
+            }
+            Err(error @ (ErrorMode::Error(_) | ErrorMode::Failure(_))) => {
+                diagnostics
+                    .borrow_mut()
+                    .emit(start..start, severity, message);
+                Err(error)
+            }
+            Err(error) => Err(error),
+        }
+    }
+}
+
+/// Replaces the state with its default value and gets the prior state.
+///
+/// Use `reset_state_to` to reset to a value other than the default.
+pub fn reset_state<I, T, E>(mut input: Stateful<I, T>) -> IResult<Stateful<I, T>, T, E>
+where
+    T: Default,
+    E: ParseError<Stateful<I, T>>,
+{
+    let previous = input.replace_state(T::default());
+    Ok((input, previous))
+}
+
+/// Replaces the state with the given value and gets the prior state.
+///
+/// This is like `reset_state`, but resets to `state` rather than the default.
+pub fn reset_state_to<I, T, E>(state: T) -> impl Fn(Stateful<I, T>) -> IResult<Stateful<I, T>, T, E>
+where
+    T: Clone,
+    E: ParseError<Stateful<I, T>>,
+{
+    move |mut input: Stateful<I, T>| {
+        let previous = input.replace_state(state.clone());
+        Ok((input, previous))
+    }
+}
+
+/// Parses all remaining input.
+///
+/// The output is located at the current position and the remaining input is
+/// empty and located at the end.
+pub fn rest_located<I, E>(input: I) -> IResult<I, I, E>
+where
+    I: InputLength + InputTake,
+    E: ParseError<I>,
+{
+    Ok(input.take_split(input.input_len()))
+}
+
+/// Parses a non-empty list of items separated by a separator and gets the
+/// span of the list.
+///
+/// The span extends from the start of the first item to the end of the last
+/// item, so it excludes any input consumed by a trailing separator. Like
+/// `separated_list1`, a separator that is not followed by an item is not
+/// consumed.
+#[allow(clippy::type_complexity)]
+pub fn separated_list1_whole_span<I, O1, O2, E, F, G>(
+    mut separator: F,
+    item: G,
+) -> impl FnMut(I) -> IResult<I, (Range<usize>, Vec<O2>), E>
+where
+    I: Clone + InputLength + Location,
+    E: ParseError<I>,
+    F: Parser<I, O1, E>,
+    G: Parser<I, O2, E>,
+{
+    let mut item = span_range(item);
+    move |input: I| {
+        let (mut input, (mut span, output)) = item(input)?;
+        let mut items = vec![output];
+        loop {
+            let len = input.input_len();
+            let remaining = match separator.parse(input.clone()) {
+                Ok((remaining, _)) => remaining,
+                Err(ErrorMode::Error(_)) => return Ok((input, (span, items))),
+                Err(error) => return Err(error),
+            };
+            if remaining.input_len() == len {
+                return Err(ErrorMode::Error(E::from_error_kind(
+                    remaining,
+                    ErrorKind::SeparatedList,
+                )));
+            }
+            match item(remaining) {
+                Ok((remaining, (next, output))) => {
+                    span.end = next.end;
+                    items.push(output);
+                    input = remaining;
+                }
+                Err(ErrorMode::Error(_)) => return Ok((input, (span, items))),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// Parses a list of items separated by a separator that disallows a missing
+/// separator.
+///
+/// When a separator is not found but an item follows, the parser fails with
+/// an error that has a zero-width span at the location where the separator
+/// was expected. Otherwise, the list ends as with `separated_list0`.
+pub fn separated_list_strict<I, O1, O2, E, F, G>(
+    mut separator: F,
+    mut item: G,
+) -> impl FnMut(I) -> IResult<I, Vec<O2>, E>
+where
+    I: Clone + InputLength + Location,
+    E: SpanError<I>,
+    F: Parser<I, O1, E>,
+    G: Parser<I, O2, E>,
+{
+    move |input: I| {
+        let mut items = Vec::new();
+        let mut input = match item.parse(input.clone()) {
+            Ok((remaining, output)) => {
+                items.push(output);
+                remaining
+            }
+            Err(ErrorMode::Error(_)) => return Ok((input, items)),
+            Err(error) => return Err(error),
+        };
+        loop {
+            let len = input.input_len();
+            let remaining = match separator.parse(input.clone()) {
+                Ok((remaining, _)) => remaining,
+                Err(ErrorMode::Error(_)) => {
+                    return match item.parse(input.clone()) {
+                        Ok(_) => {
+                            let location = input.location();
+                            Err(ErrorMode::Error(E::from_span(
+                                input,
+                                location..location,
+                                ErrorKind::SeparatedList,
+                            )))
+                        }
+                        Err(ErrorMode::Error(_)) => Ok((input, items)),
+                        Err(error) => Err(error),
+                    };
+                }
+                Err(error) => return Err(error),
+            };
+            if remaining.input_len() == len {
+                return Err(ErrorMode::Error(E::from_error_kind(
+                    remaining,
+                    ErrorKind::SeparatedList,
+                )));
+            }
+            match item.parse(remaining) {
+                Ok((remaining, output)) => {
+                    items.push(output);
+                    input = remaining;
+                }
+                Err(ErrorMode::Error(_)) => return Ok((input, items)),
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// Parses a pair separated by a separator and gets the span of the pair.
+///
+/// The span includes everything consumed by the separator, such as any
+/// surrounding whitespace.
+#[allow(clippy::type_complexity)]
+pub fn separated_pair_span<I, O1, O2, O3, E, F, G, H>(
+    first: F,
+    separator: G,
+    second: H,
+) -> impl FnMut(I) -> IResult<I, (Range<usize>, (O1, O3)), E>
+where
+    I: Location,
+    E: ParseError<I>,
+    F: Parser<I, O1, E>,
+    G: Parser<I, O2, E>,
+    H: Parser<I, O3, E>,
+{
+    span_range(separated_pair(first, separator, second))
+}
+
+/// Adjusts spans following an insertion or deletion of `delta` bytes at
+/// `edit_at`.
+///
+/// Spans that end at or before `edit_at` are untouched and spans that begin at
+/// or after `edit_at` are shifted. Spans that contain `edit_at` are widened or
+/// narrowed. For deletions, positions within the deleted region collapse to
+/// `edit_at`.
+pub fn shift_spans(spans: &mut [Range<usize>], edit_at: usize, delta: isize) {
+    let shift = |point: usize| {
+        if delta < 0 {
+            point.saturating_sub(delta.unsigned_abs()).max(edit_at)
+        }
+        else {
+            point.saturating_add(delta.unsigned_abs())
+        }
+    };
+    for span in spans.iter_mut() {
+        if span.start >= edit_at {
+            span.start = shift(span.start);
+            span.end = shift(span.end);
+        }
+        else if span.end > edit_at {
+            span.end = shift(span.end);
+        }
+    }
+}
+
+/// Parses an optional sign followed by a number and gets the spans of both.
+///
+/// The sign is either `+` or `-` and its span is `None` when absent.
+#[allow(clippy::type_complexity)]
+pub fn signed_spanned<I, O, E, F>(
+    number: F,
+) -> impl FnMut(I) -> IResult<I, (Option<Range<usize>>, Spanned<O>), E>
+where
+    I: InputIter + InputTake + Location,
+    <I as InputIter>::Item: AsChar,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    let mut number = span_range(number);
+    move |input: I| {
+        let start = input.location();
+        let is_sign = input
+            .iter_elements()
+            .next()
+            .is_some_and(|item| matches!(item.as_char(), '+' | '-'));
+        let (input, sign) = if is_sign {
+            let (remaining, _) = input.take_split(1);
+            (remaining, Some(start..(start + 1)))
+        }
+        else {
+            (input, None)
+        };
+        let (input, (span, output)) = number(input)?;
+        Ok((input, (sign, Spanned::new(span, output))))
+    }
+}
+
+/// Applies a parser and gets the line and the range of columns of the input
+/// that it consumed if it is on a single line.
+///
+/// The line and columns are `None` if the consumed input spans more than one
+/// line, including when it ends with a line terminator.
+#[allow(clippy::type_complexity)]
+pub fn single_line_span<'x, I, O, E, F>(
+    index: &'x LineIndex,
+    parser: F,
+) -> impl FnMut(I) -> IResult<I, (Option<(usize, Range<usize>)>, O), E> + 'x
+where
+    I: Location + 'x,
+    O: 'x,
+    E: ParseError<I> + 'x,
+    F: Parser<I, O, E> + 'x,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        let (remaining, (span, output)) = parser(input)?;
+        let start = index.line_col(span.start);
+        let end = index.line_col(span.end);
+        let line = (start.line == end.line).then_some((start.line, start.column..end.column));
+        Ok((remaining, (line, output)))
+    }
+}
+
+/// Skips whitespace and comments and gets the span of the skipped input.
+///
+/// Comments are recognized per `style`. The span is empty if there is no
+/// trivia. When a block comment is not terminated, the error spans the
+/// remaining input from the start of the comment.
+pub fn skip_trivia<I, E>(style: CommentStyle) -> impl Fn(I) -> IResult<I, Range<usize>, E>
+where
+    I: Clone
+        + Compare<&'static str>
+        + InputIter
+        + InputLength
+        + InputTake
+        + Location
+        + Slice<RangeFrom<usize>>,
+    <I as InputIter>::Item: AsChar,
+    E: SpanError<I>,
+{
+    move |input: I| {
+        let start = input.location();
+        let mut input = input;
+        loop {
+            let n = input
+                .position(|item| !item.as_char().is_whitespace())
+                .unwrap_or_else(|| input.input_len());
+            input = input.take_split(n).0;
+            if let Some(prefix) = style.line {
+                if !prefix.is_empty() && input.compare(prefix) == CompareResult::Ok {
+                    let n = input
+                        .position(|item| item.as_char() == '\n')
+                        .unwrap_or_else(|| input.input_len());
+                    input = input.take_split(n).0;
+                    continue;
+                }
+            }
+            if let Some((open, close)) = style.block {
+                if !open.is_empty() && input.compare(open) == CompareResult::Ok {
+                    let mut depth = 0usize;
+                    let mut end = None;
+                    let mut next = 0;
+                    for (n, _) in input.iter_indices() {
+                        if n < next {
+                            continue;
+                        }
+                        let rest = input.slice(n..);
+                        if depth > 0 && rest.compare(close) == CompareResult::Ok {
+                            depth -= 1;
+                            next = n + close.len();
+                            if depth == 0 {
+                                end = Some(next);
+                                break;
+                            }
+                        }
+                        else if (depth == 0 || style.nested)
+                            && rest.compare(open) == CompareResult::Ok
+                        {
+                            depth += 1;
+                            next = n + open.len();
+                        }
+                    }
+                    match end {
+                        Some(n) => {
+                            input = input.take_split(n).0;
+                            continue;
+                        }
+                        None => {
+                            let location = input.location();
+                            let len = input.input_len();
+                            return Err(ErrorMode::Error(E::from_span(
+                                input,
+                                location..(location + len),
+                                ErrorKind::TakeUntil,
+                            )));
+                        }
+                    }
+                }
+            }
+            break;
+        }
+        let end = input.location();
+        Ok((input, start..end))
+    }
+}
+
+/// Skips whitespace and gets its span.
+///
+/// Never fails. When there is no whitespace, the span is zero-width at the
+/// current location.
+pub fn skip_whitespace_span<I, E>(input: I) -> IResult<I, (Range<usize>, ()), E>
+where
+    I: InputIter + InputLength + InputTake + Location,
+    <I as InputIter>::Item: AsChar,
+    E: ParseError<I>,
+{
+    let start = input.location();
+    let n = input
+        .position(|item| !item.as_char().is_whitespace())
+        .unwrap_or_else(|| input.input_len());
+    let (remaining, _) = input.take_split(n);
+    let end = remaining.location();
+    Ok((remaining, (start..end, ())))
+}
+
+/// Parses a soft keyword and gets its span.
+///
+/// Fails unless the keyword is enabled in the state and is not followed by an
+/// alphanumeric character or underscore, so that parsers can fall back to
+/// identifiers in other contexts.
+pub fn soft_keyword<I, E>(
+    word: &'static str,
+) -> impl Fn(Stateful<I, SoftKeywords>) -> IResult<Stateful<I, SoftKeywords>, Range<usize>, E>
+where
+    Stateful<I, SoftKeywords>:
+        Compare<&'static str> + InputIter + InputTake + Location + Slice<RangeFrom<usize>>,
+    <Stateful<I, SoftKeywords> as InputIter>::Item: AsChar,
+    E: ParseError<Stateful<I, SoftKeywords>>,
+{
+    move |input: Stateful<I, SoftKeywords>| {
+        let is_keyword = input.state.is_enabled(word)
+            && input.compare(word) == CompareResult::Ok
+            && input
+                .slice(word.len()..)
+                .iter_elements()
+                .next()
+                .map_or(true, |item| {
+                    let c = item.as_char();
+                    !(c.is_alphanumeric() || c == '_')
+                });
+        if is_keyword {
+            let start = input.location();
+            let (remaining, _) = input.take_split(word.len());
+            Ok((remaining, start..(start + word.len())))
+        }
+        else {
+            Err(ErrorMode::Error(E::from_error_kind(input, ErrorKind::Tag)))
+        }
+    }
+}
+
+pub fn span<I, O, E, F>(mut parser: F) -> impl FnMut(I) -> IResult<I, ((usize, usize), O), E>
+where
+    I: Clone + Location,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        let start = input.location();
+        parser.parse(input).map(move |(remaining, output)| {
+            let end = remaining.location();
+            (remaining, ((start, end.saturating_sub(start)), output))
+        })
+    }
+}
+
+/// Gets a digest of the content of `source` in the given span.
+///
+/// The digest is the 64-bit FNV-1a hash of the bytes in the span, so it is
+/// stable across processes and platforms and can identify unchanged regions
+/// between edits.
+///
+/// # Panics
+///
+/// Panics if the span is out of bounds.
+pub fn span_digest(source: &str, span: Range<usize>) -> u64 {
+    source.as_bytes()[span]
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |digest, &byte| {
+            (digest ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// Applies a parser and gets the lines and columns of the start and end of the
+/// input that it consumed.
+pub fn span_line_col<'x, I, O, E, F>(
+    index: &'x LineIndex,
+    parser: F,
+) -> impl FnMut(I) -> IResult<I, (LineColumn, LineColumn, O), E> + 'x
+where
+    I: Clone + Location + 'x,
+    O: 'x,
+    E: ParseError<I> + 'x,
+    F: Parser<I, O, E> + 'x,
+{
+    let mut parser = span(parser);
+    move |input: I| {
+        parser
+            .parse(input)
+            .map(|(remaining, ((start, len), output))| {
+                let end = index.line_col(start + len);
+                let start = index.line_col(start);
+                (remaining, (start, end, output))
+            })
+    }
+}
+
+fn span_range<I, O, E, F>(mut parser: F) -> impl FnMut(I) -> IResult<I, (Range<usize>, O), E>
+where
+    I: Location,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        let start = input.location();
+        parser.parse(input).map(move |(remaining, output)| {
+            let end = remaining.location();
+            (remaining, (start..end, output))
+        })
+    }
+}
+
+/// Applies a sequence of parsers and gets their outputs and the span of each
+/// output.
+///
+/// The spans are in the order of the parsers, so the span of the entire
+/// sequence extends from the start of the first span to the end of the last.
+#[allow(clippy::type_complexity)]
+pub fn spanned_tuple<I, O, E, T>(
+    mut parsers: T,
+) -> impl FnMut(I) -> IResult<I, (O, Vec<Range<usize>>), E>
+where
+    I: Location,
+    E: ParseError<I>,
+    T: SpannedTuple<I, O, E>,
+{
+    move |input: I| parsers.parse_spanned(input)
+}
+
+/// Applies a parser and gets the column at which its input begins.
+pub fn start_column<'x, I, O, E, F>(
+    index: &'x LineIndex,
+    mut parser: F,
+) -> impl FnMut(I) -> IResult<I, (usize, O), E> + 'x
+where
+    I: Location + 'x,
+    O: 'x,
+    E: ParseError<I> + 'x,
+    F: Parser<I, O, E> + 'x,
+{
+    move |input: I| {
+        let column = index.line_col(input.location()).column;
+        parser
+            .parse(input)
+            .map(|(remaining, output)| (remaining, (column, output)))
+    }
+}
+
+/// Applies a parser and gets a report of how it changed the state.
+///
+/// The report is computed from the states before and after the parser is
+/// applied.
+#[allow(clippy::type_complexity)]
+pub fn state_diff<I, T, O, R, E, F, D>(
+    mut parser: F,
+    diff: D,
+) -> impl FnMut(Stateful<I, T>) -> IResult<Stateful<I, T>, (O, R), E>
+where
+    T: Clone,
+    E: ParseError<Stateful<I, T>>,
+    F: Parser<Stateful<I, T>, O, E>,
+    D: Fn(&T, &T) -> R,
+{
+    move |input: Stateful<I, T>| {
+        let before = input.state.clone();
+        let (remaining, output) = parser.parse(input)?;
+        let report = diff(&before, &remaining.state);
+        Ok((remaining, (output, report)))
+    }
+}
+
+/// Parses a list of items separated by a separator that disallows a trailing
+/// separator.
+///
+/// When a separator is not followed by an item, the parser fails with an
+/// error that spans the separator.
+pub fn strict_list<I, O1, O2, E, F, G>(
+    separator: F,
+    mut item: G,
+) -> impl FnMut(I) -> IResult<I, Vec<O2>, E>
+where
+    I: Clone + InputLength + Location,
+    E: SpanError<I>,
+    F: Parser<I, O1, E>,
+    G: Parser<I, O2, E>,
+{
+    let mut separator = span_range(separator);
+    move |input: I| {
+        let mut items = Vec::new();
+        let mut input = match item.parse(input.clone()) {
+            Ok((remaining, output)) => {
+                items.push(output);
+                remaining
+            }
+            Err(ErrorMode::Error(_)) => return Ok((input, items)),
+            Err(error) => return Err(error),
+        };
+        loop {
+            let len = input.input_len();
+            let (remaining, (span, _)) = match separator(input.clone()) {
+                Ok(output) => output,
+                Err(ErrorMode::Error(_)) => return Ok((input, items)),
+                Err(error) => return Err(error),
+            };
+            if remaining.input_len() == len {
+                return Err(ErrorMode::Error(E::from_error_kind(
+                    remaining,
+                    ErrorKind::SeparatedList,
+                )));
+            }
+            match item.parse(remaining) {
+                Ok((remaining, output)) => {
+                    items.push(output);
+                    input = remaining;
+                }
+                Err(ErrorMode::Error(_)) => {
+                    return Err(ErrorMode::Error(E::from_span(
+                        input,
+                        span,
+                        ErrorKind::SeparatedList,
+                    )));
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+
+/// Locates `source` after any leading UTF-8 byte order mark.
+///
+/// The byte order mark is not part of the input, but locations are relative
+/// to the start of `source`, so the first character following a byte order
+/// mark is at location three. This keeps locations consistent with offsets in
+/// the original file.
+pub fn strip_bom(source: &str) -> Located<'_, str> {
+    match source.strip_prefix('\u{FEFF}') {
+        Some(data) => Located::from((data, source.len() - data.len())),
+        None => Located::from(source),
+    }
+}
+
+/// Replaces the state with the given value and gets the prior state.
+/// See `Stateful::replace_state`.
+pub fn swap_state<I, T, E>(state: T) -> impl FnMut(Stateful<I, T>) -> IResult<Stateful<I, T>, T, E>
+where
+    T: Clone,
+    E: ParseError<Stateful<I, T>>,
+{
+    move |mut input: Stateful<I, T>| {
+        let previous = input.replace_state(state.clone());
+        Ok((input, previous))
+    }
+}
+
+/// Matches a tag and gets its span and the matched fragment.
+pub fn tag_located<I, T, E>(value: T) -> impl Fn(I) -> IResult<I, (Range<usize>, I), E>
+where
+    I: Compare<T> + InputTake + Location,
+    T: Clone + InputLength,
+    E: ParseError<I>,
+{
+    move |input: I| {
+        let start = input.location();
+        tag(value.clone())(input).map(|(remaining, fragment)| {
+            let end = remaining.location();
+            (remaining, (start..end, fragment))
+        })
+    }
+}
+
+/// Applies the first matching parser in a choice and gets its index and the
+/// span of the input that it consumed.
+#[allow(clippy::type_complexity)]
+pub fn tagged_alt<I, O, E, A>(
+    mut branches: A,
+) -> impl FnMut(I) -> IResult<I, (usize, Range<usize>, O), E>
+where
+    I: Clone + Location,
+    E: ParseError<I>,
+    A: TaggedAlt<I, O, E>,
+{
+    move |input: I| {
+        let start = input.location();
+        branches.choice(input).map(|(remaining, (n, output))| {
+            let end = remaining.location();
+            (remaining, (n, start..end, output))
+        })
+    }
+}
+
+/// Like `take_till_span`, but fails at the current location if no input is
+/// consumed.
+pub fn take_till1_span<I, E, P>(predicate: P) -> impl FnMut(I) -> IResult<I, (Range<usize>, I), E>
+where
+    I: InputTakeAtPosition + Location,
+    E: ParseError<I>,
+    P: Fn(<I as InputTakeAtPosition>::Item) -> bool,
+{
+    span_range(take_till1(predicate))
+}
+
+/// Takes input until a predicate is satisfied and gets its span.
+///
+/// When the predicate is satisfied at the current location, no input is consumed
+/// and the span is zero-width.
+pub fn take_till_span<I, E, P>(predicate: P) -> impl FnMut(I) -> IResult<I, (Range<usize>, I), E>
+where
+    I: InputTakeAtPosition + Location,
+    E: ParseError<I>,
+    P: Fn(<I as InputTakeAtPosition>::Item) -> bool,
+{
+    span_range(take_till(predicate))
+}
+
+/// Like `take_while_span`, but fails at the current location if no input is
+/// consumed.
+pub fn take_while1_span<I, E, P>(predicate: P) -> impl FnMut(I) -> IResult<I, (Range<usize>, I), E>
+where
+    I: InputTakeAtPosition + Location,
+    E: ParseError<I>,
+    P: Fn(<I as InputTakeAtPosition>::Item) -> bool,
+{
+    span_range(take_while1(predicate))
+}
+
+/// Takes input while a predicate is satisfied and gets its span.
+///
+/// When the predicate is not satisfied at the current location, no input is
+/// consumed and the span is zero-width.
+pub fn take_while_span<I, E, P>(predicate: P) -> impl FnMut(I) -> IResult<I, (Range<usize>, I), E>
+where
+    I: InputTakeAtPosition + Location,
+    E: ParseError<I>,
+    P: Fn(<I as InputTakeAtPosition>::Item) -> bool,
+{
+    span_range(take_while(predicate))
+}
+
+/// Parses the remainder of the current line and gets its span.
+///
+/// The line terminator (`\n`, `\r\n`, or `\r`) is not consumed. On the last
+/// line, the remainder extends to the end of input.
+pub fn to_end_of_line_span<I, E>(input: I) -> IResult<I, (Range<usize>, I), E>
+where
+    I: InputIter + InputLength + InputTake + Location,
+    <I as InputIter>::Item: AsChar,
+    E: ParseError<I>,
+{
+    let start = input.location();
+    let n = input
+        .position(|item| matches!(item.as_char(), '\n' | '\r'))
+        .unwrap_or_else(|| input.input_len());
+    let (remaining, line) = input.take_split(n);
+    let end = remaining.location();
+    Ok((remaining, (start..end, line)))
+}
+
+/// Converts a span in `source` into zero-based lines and UTF-16 columns, as
+/// used by the Language Server Protocol.
+pub fn to_lsp_range(source: &str, span: Range<usize>) -> (LineColumn, LineColumn) {
+    let position = |offset: usize| {
+        let mut offset = offset.min(source.len());
+        while !source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let prefix = &source[..offset];
+        let start = prefix.rfind('\n').map_or(0, |n| n + 1);
+        LineColumn {
+            line: prefix.matches('\n').count(),
+            column: prefix[start..].encode_utf16().count(),
+        }
+    };
+    (position(span.start), position(span.end))
+}
+
+/// Converts a span into its start and length.
+///
+/// This is the form of the spans of `span` and byte ranges used by tools such
+/// as `proc_macro2`.
+#[cfg(feature = "codegen")]
+pub fn to_start_len(span: Range<usize>) -> (usize, usize) {
+    (span.start, span.len())
+}
+
+/// Parses a maximal run of whitespace or a line comment.
+///
+/// Line comments begin with `prefix` and end before the next newline, which
+/// is parsed as whitespace.
+pub fn trivia<I, E>(prefix: &'static str) -> impl Fn(I) -> IResult<I, Spanned<Trivia>, E>
+where
+    I: Compare<&'static str> + InputIter + InputLength + InputTake + Location,
+    <I as InputIter>::Item: AsChar,
+    E: ParseError<I>,
+{
+    move |input: I| {
+        let start = input.location();
+        let (n, kind) = if !prefix.is_empty() && input.compare(prefix) == CompareResult::Ok {
+            let n = input
+                .position(|item| item.as_char() == '\n')
+                .unwrap_or_else(|| input.input_len());
+            (n, Trivia::LineComment)
+        }
+        else {
+            let n = input
+                .position(|item| !item.as_char().is_whitespace())
+                .unwrap_or_else(|| input.input_len());
+            (n, Trivia::Whitespace)
+        };
+        if n == 0 {
+            Err(ErrorMode::Error(E::from_error_kind(
+                input,
+                ErrorKind::Space,
+            )))
+        }
+        else {
+            let (remaining, _) = input.take_split(n);
+            let end = remaining.location();
+            Ok((remaining, Spanned::new(start..end, kind)))
+        }
+    }
+}
+
+/// Fails with an `Unexpected` error that spans the next token and refers to
+/// the labels of the expected tokens.
+///
+/// The next token is a maximal run of alphanumeric characters and underscores
+/// or otherwise a single character. This is typically used as the last
+/// alternative of a choice.
+pub fn unexpected<I, O, E>(expected: &'static [&'static str]) -> impl Fn(I) -> IResult<I, O, E>
+where
+    I: InputIter + InputLength + Location,
+    <I as InputIter>::Item: AsChar,
+    E: FromExternalError<I, Unexpected> + ParseError<I>,
+{
+    move |input: I| {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let mut token = String::new();
+        let mut word = false;
+        let mut n = input.input_len();
+        for (index, c) in input
+            .iter_indices()
+            .map(|(index, item)| (index, item.as_char()))
+        {
+            if token.is_empty() {
+                word = is_word(c);
+                token.push(c);
+            }
+            else if word && is_word(c) {
+                token.push(c);
+            }
+            else {
+                n = index;
+                break;
+            }
+        }
+        let start = input.location();
+        Err(ErrorMode::Error(E::from_external_error(
+            input,
+            ErrorKind::Fail,
+            Unexpected {
+                token,
+                span: start..(start + n),
+                expected: expected.to_vec(),
+            },
+        )))
+    }
+}
+
+/// Requires that the outputs of a parser are unique.
+///
+/// The locations of keys are recorded in the state. When a key has already
+/// been recorded, the parser fails with a `DuplicateKey` that refers to the
+/// locations of both occurrences and the error is located at the fragment
+/// matched by the parser.
+#[allow(clippy::type_complexity)]
+pub fn unique_key<I, O, E, F>(
+    mut parser: F,
+) -> impl FnMut(Stateful<I, HashMap<String, usize>>) -> IResult<Stateful<I, HashMap<String, usize>>, O, E>
+where
+    I: Clone + Location + Offset + Slice<RangeTo<usize>>,
+    O: AsRef<str>,
+    E: FromExternalError<Stateful<I, HashMap<String, usize>>, DuplicateKey>
+        + ParseError<Stateful<I, HashMap<String, usize>>>,
+    F: Parser<Stateful<I, HashMap<String, usize>>, O, E>,
+{
+    move |input: Stateful<I, HashMap<String, usize>>| {
+        let location = input.location();
+        let (mut remaining, output) = parser.parse(input.clone())?;
+        let key = output.as_ref();
+        if let Some(&original) = remaining.state.get(key) {
+            let n = input.offset(&remaining);
+            Err(ErrorMode::Error(E::from_external_error(
+                input.slice(..n),
+                ErrorKind::Verify,
+                DuplicateKey {
+                    key: key.to_owned(),
+                    original,
+                    duplicate: location,
+                },
+            )))
+        }
+        else {
+            remaining.state.insert(key.to_owned(), location);
+            Ok((remaining, output))
+        }
+    }
+}
+
+/// Parses bytes up to and including a sentinel and gets the span of the
+/// bytes preceding the sentinel.
+///
+/// The output excludes the sentinel. Fails if the sentinel is not found.
+pub fn until_sentinel_span<I, E>(sentinel: u8) -> impl Fn(I) -> IResult<I, (Range<usize>, I), E>
+where
+    I: InputIter<Item = u8> + InputTake + Location,
+    E: ParseError<I>,
+{
+    move |input: I| match input.position(|item| item == sentinel) {
+        Some(n) => {
+            let start = input.location();
+            let (remaining, content) = input.take_split(n);
+            let (remaining, _) = remaining.take_split(1);
+            Ok((remaining, (start..(start + n), content)))
+        }
+        None => Err(ErrorMode::Error(E::from_error_kind(
+            input,
+            ErrorKind::TakeUntil,
+        ))),
+    }
+}
+
+/// Parses input up to, but excluding, the first location at which a lookahead
+/// parser matches and gets its span.
+///
+/// The input matched by the lookahead parser is not consumed. When the
+/// lookahead parser never matches, the error spans the remaining input.
+pub fn up_to_span<I, O, E, F>(mut lookahead: F) -> impl FnMut(I) -> IResult<I, (Range<usize>, I), E>
+where
+    I: Clone + InputIter + InputLength + InputTake + Location + Slice<RangeFrom<usize>>,
+    E: SpanError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        let start = input.location();
+        let len = input.input_len();
+        for n in input.iter_indices().map(|(n, _)| n).chain(iter::once(len)) {
+            match lookahead.parse(input.slice(n..)) {
+                Ok(_) => {
+                    let (remaining, output) = input.take_split(n);
+                    return Ok((remaining, (start..(start + n), output)));
+                }
+                Err(ErrorMode::Error(_)) => {}
+                Err(error) => return Err(error),
+            }
+        }
+        Err(ErrorMode::Error(E::from_span(
+            input,
+            start..(start + len),
+            ErrorKind::TakeUntil,
+        )))
+    }
+}
+
+/// Checks that spans are contiguous and cover a source of length
+/// `source_len`.
+///
+/// This is useful for lossless parsers, where the leaves of a tree must
+/// reconstruct the source. Fails with the first gap, overlap, or span that
+/// extends beyond the source.
+pub fn validate_contiguity(
+    leaves: &[Range<usize>],
+    source_len: usize,
+) -> Result<(), ContiguityError> {
+    let mut end = 0;
+    for (index, span) in leaves.iter().enumerate() {
+        if span.start > end {
+            return Err(ContiguityError::Gap {
+                index,
+                span: end..span.start,
+            });
+        }
+        if span.start < end {
+            return Err(ContiguityError::Overlap {
+                index,
+                span: span.start..end.min(span.end),
+            });
+        }
+        if span.end > source_len {
+            return Err(ContiguityError::OutOfBounds {
+                index,
+                span: source_len.max(span.start)..span.end,
+            });
+        }
+        end = span.end;
+    }
+    if end < source_len {
+        Err(ContiguityError::Gap {
+            index: leaves.len(),
+            span: end..source_len,
+        })
+    }
+    else {
+        Ok(())
+    }
+}
+
+/// Applies a parser and gets a value spanning the input that it consumed.
+pub fn value_spanned<I, O1, O2, E, F>(
+    value: O2,
+    parser: F,
+) -> impl FnMut(I) -> IResult<I, Spanned<O2>, E>
+where
+    I: Location,
+    O2: Clone,
+    E: ParseError<I>,
+    F: Parser<I, O1, E>,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        parser(input).map(|(remaining, (span, _))| (remaining, Spanned::new(span, value.clone())))
+    }
+}
+
+/// Applies a parser and verifies its output with a predicate.
+///
+/// Unlike `verify`, the input of errors is only the fragment matched by the
+/// parser and errors are given the rejected output and its span.
+pub fn verify_spanned<I, O, E, F, P>(parser: F, predicate: P) -> impl FnMut(I) -> IResult<I, O, E>
+where
+    I: Clone + Location + Offset + Slice<RangeTo<usize>>,
+    E: FromExternalError<I, Spanned<O>> + ParseError<I>,
+    F: Parser<I, O, E>,
+    P: Fn(&O) -> bool,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        let (remaining, (span, output)) = parser(input.clone())?;
+        if predicate(&output) {
+            Ok((remaining, output))
+        }
+        else {
+            let n = input.offset(&remaining);
+            Err(ErrorMode::Error(E::from_external_error(
+                input.slice(..n),
+                ErrorKind::Verify,
+                Spanned::new(span, output),
+            )))
+        }
+    }
+}
+
+/// Fails with `ErrorKind::Verify` if the state does not satisfy a predicate.
+///
+/// No input is consumed.
+pub fn verify_state<I, T, E, P>(
+    predicate: P,
+) -> impl FnMut(Stateful<I, T>) -> IResult<Stateful<I, T>, (), E>
+where
+    E: ParseError<Stateful<I, T>>,
+    P: Fn(&T) -> bool,
+{
+    move |input: Stateful<I, T>| {
+        if predicate(&input.state) {
+            Ok((input, ()))
+        }
+        else {
+            Err(ErrorMode::Error(E::from_error_kind(
+                input,
+                ErrorKind::Verify,
+            )))
+        }
+    }
+}
+
+/// Applies a parser and records a warning with the span of the input that it
+/// consumed in the state.
+///
+/// The output of the parser is unchanged and no warning is recorded if the
+/// parser fails.
+#[allow(clippy::type_complexity)]
+pub fn warn_span<I, O, E, F>(
+    message: &'static str,
+    parser: F,
+) -> impl FnMut(
+    Stateful<I, Vec<Spanned<&'static str>>>,
+) -> IResult<Stateful<I, Vec<Spanned<&'static str>>>, O, E>
+where
+    Stateful<I, Vec<Spanned<&'static str>>>: Location,
+    E: ParseError<Stateful<I, Vec<Spanned<&'static str>>>>,
+    F: Parser<Stateful<I, Vec<Spanned<&'static str>>>, O, E>,
+{
+    let mut parser = span_range(parser);
+    move |input: Stateful<I, Vec<Spanned<&'static str>>>| {
+        let (mut remaining, (span, output)) = parser(input)?;
+        remaining.state.push(Spanned::new(span, message));
+        Ok((remaining, output))
+    }
+}
+
+/// Parses any number of attributes followed by an item and gets the span of
+/// each attribute and of the item.
+///
+/// The span of the item excludes its attributes.
+#[allow(clippy::type_complexity)]
+pub fn with_attributes<I, A, O, E, F, G>(
+    attribute: F,
+    item: G,
+) -> impl FnMut(I) -> IResult<I, (Vec<Spanned<A>>, Spanned<O>), E>
+where
+    I: Clone + InputLength + Location,
+    E: ParseError<I>,
+    F: Parser<I, A, E>,
+    G: Parser<I, O, E>,
+{
+    let mut attribute = span_range(attribute);
+    let mut item = span_range(item);
+    move |mut input: I| {
+        let mut attributes = Vec::new();
+        loop {
+            let len = input.input_len();
+            match attribute(input.clone()) {
+                Ok((remaining, (span, output))) => {
+                    if remaining.input_len() == len {
+                        return Err(ErrorMode::Error(E::from_error_kind(
+                            remaining,
+                            ErrorKind::Many0,
+                        )));
+                    }
+                    attributes.push(Spanned::new(span, output));
+                    input = remaining;
+                }
+                Err(ErrorMode::Error(_)) => break,
+                Err(error) => return Err(error),
+            }
+        }
+        let (remaining, (span, output)) = item(input)?;
+        Ok((remaining, (attributes, Spanned::new(span, output))))
+    }
+}
+
+/// Parses an optional doc comment followed by an item and gets the span of
+/// the doc comment and of the item.
+///
+/// The span of the doc comment is that of the input consumed by `doc`, which
+/// typically includes its markers.
+#[allow(clippy::type_complexity)]
+pub fn with_doc_comment<I, D, O, E, F, G>(
+    doc: F,
+    item: G,
+) -> impl FnMut(I) -> IResult<I, (Option<Spanned<D>>, Spanned<O>), E>
+where
+    I: Clone + Location,
+    E: ParseError<I>,
+    F: Parser<I, D, E>,
+    G: Parser<I, O, E>,
+{
+    let mut doc = span_range(doc);
+    let mut item = span_range(item);
+    move |input: I| {
+        let (input, doc) = match doc(input.clone()) {
+            Ok((remaining, (span, output))) => (remaining, Some(Spanned::new(span, output))),
+            Err(ErrorMode::Error(_)) => (input, None),
+            Err(error) => return Err(error),
+        };
+        let (remaining, (span, output)) = item(input)?;
+        Ok((remaining, (doc, Spanned::new(span, output))))
+    }
+}
+
+/// Applies a parser and gets an identifier of its output derived from a kind
+/// and the span of the input that it consumed.
+///
+/// Identifiers are stable across parses of the same input, so nodes with the
+/// same kind and span have the same identifier. Nodes that move have different
+/// identifiers. Identifiers are hashes and are not guaranteed to be stable
+/// across versions of Rust.
+pub fn with_node_id<I, K, O, E, F>(
+    kind: K,
+    parser: F,
+) -> impl FnMut(I) -> IResult<I, (NodeId, Spanned<O>), E>
+where
+    I: Location,
+    K: Hash,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        parser(input).map(|(remaining, (span, output))| {
+            let mut hasher = DefaultHasher::new();
+            (&kind, span.start, span.end).hash(&mut hasher);
+            (
+                remaining,
+                (NodeId(hasher.finish()), Spanned::new(span, output)),
+            )
+        })
+    }
+}
+
+/// Runs a parser with the current column as the reference column in the
+/// state and then restores the prior reference column.
+///
+/// This supports the offside rule of layout-sensitive grammars in conjunction
+/// with [`offside_eq`] and [`offside_gt`].
+pub fn with_reference_column<I, O, E, F>(
+    mut parser: F,
+) -> impl FnMut(Stateful<I, usize>) -> IResult<Stateful<I, usize>, O, E>
+where
+    I: FullLocation,
+    E: ParseError<Stateful<I, usize>>,
+    F: Parser<Stateful<I, usize>, O, E>,
+{
+    move |mut input: Stateful<I, usize>| {
+        let (_, _, column) = input.full_location();
+        let previous = input.replace_state(column);
+        parser.parse(input).map(move |(mut remaining, output)| {
+            remaining.replace_state(previous);
+            (remaining, output)
+        })
+    }
+}
+
+/// Runs a parser with the given state and then restores the prior state.
+///
+/// The prior state is restored in the remaining input when the parser
+/// succeeds. When the parser fails, the prior state is not restored in the
+/// error, because the input of an error is opaque to this combinator, so
+/// errors may carry input with the reset state. However, the input given to
+/// the combinator is never modified, so any backtracking observes the prior
+/// state regardless.
+pub fn with_reset_state<I, T, O, E, F>(
+    state: T,
+    mut parser: F,
+) -> impl FnMut(Stateful<I, T>) -> IResult<Stateful<I, T>, O, E>
+where
+    T: Clone,
+    E: ParseError<Stateful<I, T>>,
+    F: Parser<Stateful<I, T>, O, E>,
+{
+    move |mut input: Stateful<I, T>| {
+        let previous = input.replace_state(state.clone());
+        parser.parse(input).map(move |(mut remaining, output)| {
+            remaining.replace_state(previous);
+            (remaining, output)
+        })
+    }
+}
+
+/// Applies a parser and gets the span of the input that remains after it.
+///
+/// The span extends from the end of the input consumed by the parser to the end
+/// of input.
+pub fn with_rest_span<I, O, E, F>(
+    mut parser: F,
+) -> impl FnMut(I) -> IResult<I, (O, Range<usize>), E>
+where
+    I: InputLength + Location,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        parser.parse(input).map(|(remaining, output)| {
+            let start = remaining.location();
+            let end = start + remaining.input_len();
+            (remaining, (output, start..end))
+        })
+    }
+}
+
+/// Applies a parser and gets the spans of the whitespace that surrounds it.
+///
+/// Whitespace before and after the parser is consumed. When there is no
+/// whitespace, the corresponding span is zero-width.
+#[allow(clippy::type_complexity)]
+pub fn with_surrounding_ws<I, O, E, F>(
+    parser: F,
+) -> impl FnMut(I) -> IResult<I, (Range<usize>, Spanned<O>, Range<usize>), E>
+where
+    I: InputIter + InputLength + InputTake + Location,
+    <I as InputIter>::Item: AsChar,
+    E: ParseError<I>,
+    F: Parser<I, O, E>,
+{
+    let mut parser = span_range(parser);
+    move |input: I| {
+        let (input, (leading, _)) = skip_whitespace_span(input)?;
+        let (input, (span, output)) = parser(input)?;
+        let (input, (trailing, _)) = skip_whitespace_span(input)?;
+        Ok((input, (leading, Spanned::new(span, output), trailing)))
+    }
+}
+
+/// Applies a parser to a window of input from `start` to `end` as if `end`
+/// were the end of input.
+///
+/// The window is in the coordinates of the input, so the spans of the parser
+/// are in the coordinates of the outer input. When the parser does not
+/// consume the entire window, the error spans the unconsumed input in the
+/// window. On success, the remaining input follows `end`. Fails if the window
+/// is not within the input.
+pub fn within_window<I, O, E, F>(
+    start: usize,
+    end: usize,
+    mut parser: F,
+) -> impl FnMut(I) -> IResult<I, O, E>
+where
+    I: InputLength + Location + Slice<Range<usize>> + Slice<RangeFrom<usize>>,
+    E: SpanError<I>,
+    F: Parser<I, O, E>,
+{
+    move |input: I| {
+        let location = input.location();
+        if start < location || end < start || end > location + input.input_len() {
+            return Err(ErrorMode::Error(E::from_error_kind(input, ErrorKind::Eof)));
+        }
+        let window = input.slice((start - location)..(end - location));
+        let (remaining, output) = parser.parse(window)?;
+        let n = remaining.input_len();
+        if n == 0 {
+            Ok((input.slice((end - location)..), output))
+        }
+        else {
+            let start = remaining.location();
+            Err(ErrorMode::Error(E::from_span(
+                remaining,
+                start..(start + n),
+                ErrorKind::Eof,
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An error that keeps the external error of `FromExternalError`.
+    #[derive(Debug)]
+    struct ExternalError<X>(Option<X>);
+
+    impl<I, X> FromExternalError<I, X> for ExternalError<X> {
+        fn from_external_error(_: I, _: ErrorKind, error: X) -> Self {
+            ExternalError(Some(error))
+        }
+    }
+
+    impl<I, X> ParseError<I> for ExternalError<X> {
+        fn from_error_kind(_: I, _: ErrorKind) -> Self {
+            ExternalError(None)
+        }
+
+        fn append(_: I, _: ErrorKind, other: Self) -> Self {
+            other
+        }
+    }
+
+    #[test]
+    fn located_into_and_from_tuple() {
+        let input = Located::new("abc", 2);
+        let (data, location): (&str, usize) = input.into();
+        assert_eq!((data, location), ("abc", 2));
+        assert_eq!(Located::from(("abc", 2)), input);
+    }
+
+    #[test]
+    fn stateful_replace_state() {
+        let mut input = Stateful::new(Located::from("ab"), 1);
+        assert_eq!(input.replace_state(2), 1);
+        assert_eq!(input.state, 2);
+
+        let (remaining, taken) = input.take_split(1);
+        let mut remaining = remaining;
+        assert_eq!(remaining.replace_state(3), 2);
+        assert_eq!(taken.state, 2);
+    }
+
+    #[test]
+    fn swap_state_gets_prior_state() {
+        let input = Stateful::new(Located::from("a"), 'x');
+        let result: IResult<_, _, LocatedError> = swap_state('y')(input);
+        let (remaining, previous) = result.unwrap();
+        assert_eq!(previous, 'x');
+        assert_eq!(remaining.state, 'y');
+    }
+
+    type Tags<'i> = Stateful<Located<'i, str>, Vec<char>>;
+
+    fn close_tag(input: Tags<'_>) -> IResult<Tags<'_>, (), LocatedError> {
+        use nom::character::complete::one_of;
+        use nom::combinator::verify;
+
+        let (input, c) = one_of(")]")(input)?;
+        let opener = if c == ')' { '(' } else { '[' };
+        let (input, _) = verify(pop, |popped: &char| *popped == opener)(input)?;
+        Ok((input, ()))
+    }
+
+    fn parse_tags(source: &str) -> Result<Vec<char>, LocatedError> {
+        use nom::branch::alt;
+        use nom::character::complete::one_of;
+        use nom::combinator::map;
+        use nom::multi::many0;
+
+        let open = map(push(one_of("([")), |_| ());
+        let mut tags = many0(alt((open, close_tag)));
+        match tags(Stateful::new(Located::from(source), Vec::new())) {
+            Ok((remaining, _)) if remaining.input_len() == 0 => Ok(remaining.state),
+            Ok((remaining, _)) => Err(LocatedError::from_error_kind(remaining, ErrorKind::Eof)),
+            Err(ErrorMode::Error(error)) | Err(ErrorMode::Failure(error)) => Err(error),
+            Err(ErrorMode::Incomplete(_)) => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn push_and_pop_balanced() {
+        assert_eq!(parse_tags("([])[]"), Ok(vec![]));
+        assert_eq!(parse_tags("(["), Ok(vec!['(', '[']));
+    }
+
+    #[test]
+    fn pop_underflow_is_located_at_closer() {
+        let input = Stateful::new(Located::new("]", 3), Vec::new());
+        match pop::<_, char, LocatedError>(input) {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.span, 3..3);
+                assert_eq!(error.kind, ErrorKind::Verify);
+            }
+            _ => panic!(),
+        }
+        assert_eq!(parse_tags("()]").unwrap_err().location(), 2);
+        assert_eq!(parse_tags("(]").unwrap_err().location(), 1);
+    }
+
+    #[test]
+    fn verify_state_passes_valid_state() {
+        let input = Stateful::new(Located::new("ab", 4), 1);
+        let result: IResult<_, _, LocatedError> = verify_state(|n: &i32| *n > 0)(input);
+        let (remaining, ()) = result.unwrap();
+        assert_eq!(remaining, input);
+    }
+
+    #[test]
+    fn verify_state_fails_at_location() {
+        use nom::bytes::complete::tag;
+        use nom::sequence::preceded;
+
+        let input = Stateful::new(Located::new("ab", 4), 0);
+        let result: IResult<_, _, LocatedError> =
+            preceded(tag("a"), verify_state(|n: &i32| *n > 0))(input);
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.span, 5..5);
+                assert_eq!(error.kind, ErrorKind::Verify);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn reset_state_gets_prior_state() {
+        let input = Stateful::new(Located::from("a"), 7);
+        let result: IResult<_, _, LocatedError> = reset_state(input);
+        let (remaining, previous) = result.unwrap();
+        assert_eq!((previous, remaining.state), (7, 0));
+
+        let result: IResult<_, _, LocatedError> = reset_state_to(3)(remaining);
+        let (remaining, previous) = result.unwrap();
+        assert_eq!((previous, remaining.state), (0, 3));
+    }
+
+    #[test]
+    fn with_reset_state_restores_state() {
+        use nom::bytes::complete::tag;
+        use nom::combinator::map;
+
+        let counted = |input| {
+            map(tag::<_, _, LocatedError>("a"), |_| ())(input).map(
+                |(mut remaining, output): (Stateful<Located<'_, str>, usize>, _)| {
+                    remaining.state += 1;
+                    (remaining, output)
+                },
+            )
+        };
+        let mut parser = with_reset_state(10, |input| {
+            let (input, ()) = counted(input)?;
+            assert_eq!(input.state, 11);
+            Ok((input, ()))
+        });
+
+        let (remaining, ()) = parser(Stateful::new(Located::from("ab"), 1)).unwrap();
+        assert_eq!(remaining.state, 1);
+
+        let input = Stateful::new(Located::from("b"), 1);
+        assert!(parser(input).is_err());
+        assert_eq!(input.state, 1);
+    }
+
+    #[test]
+    fn shift_spans_after_insertion() {
+        let mut spans = [0..2, 2..4, 3..6, 6..8];
+        shift_spans(&mut spans, 4, 3);
+        assert_eq!(spans, [0..2, 2..4, 3..9, 9..11]);
+    }
+
+    #[test]
+    fn shift_spans_after_deletion() {
+        let mut spans = [0..2, 3..6, 4..5, 7..9];
+        shift_spans(&mut spans, 4, -2);
+        assert_eq!(spans, [0..2, 3..4, 4..4, 5..7]);
+
+        let mut spans = [1..3, 6..7];
+        shift_spans(&mut spans, 0, -5);
+        assert_eq!(spans, [0..0, 1..2]);
+    }
+
+    // The fuzz target in `fuzz` guards these invariants under arbitrary input, but
+    // requires a nightly toolchain. This checks the same invariants for every split
+    // of a small input.
+    #[test]
+    fn located_split_invariants() {
+        let data = "aé\nb".as_bytes();
+        let base = 5;
+        let end = base + data.len();
+        let origin = Located::new(data, base);
+        for n in 0..=data.len() {
+            for m in 0..=(data.len() - n) {
+                let (input, _) = origin.take_split(n);
+                let (remaining, taken) = input.take_split(m);
+                assert!(remaining.location() >= input.location());
+                assert_eq!(taken.location(), input.location());
+                assert_eq!(input.offset(&remaining), m);
+                assert_eq!(origin.offset(&remaining), remaining.location() - base);
+                assert!(remaining.location() + remaining.input_len() <= end);
+                assert_eq!(taken.location() + taken.input_len(), remaining.location());
+                assert_eq!(input.take(m).location(), input.location());
+            }
+        }
+    }
+
+    #[test]
+    fn escaped_located_gets_source_span() {
+        use nom::branch::alt;
+        use nom::bytes::complete::tag;
+        use nom::character::complete::alpha1;
+        use nom::combinator::value;
+
+        let mut parser = escaped_located(
+            alpha1,
+            '\\',
+            alt((
+                value("\n", tag("n")),
+                value("\\", tag("\\")),
+                value("\"", tag("\"")),
+            )),
+        );
+        let input = Located::new(r#"a\nb\\c\"d""#, 10);
+        let result: IResult<_, (Range<usize>, String), LocatedError> = parser(input);
+        let (remaining, (span, decoded)) = result.unwrap();
+        assert_eq!(span, 10..20);
+        assert_eq!(decoded, "a\nb\\c\"d");
+        assert_eq!(&*remaining, "\"");
+        assert_eq!(remaining.location(), 20);
+    }
+
+    #[test]
+    fn tag_located_gets_span_and_fragment() {
+        let result: IResult<_, _, LocatedError> = tag_located("let")(Located::new("let x", 4));
+        let (remaining, (span, fragment)) = result.unwrap();
+        assert_eq!(span, 4..7);
+        assert_eq!(&*fragment, "let");
+        assert_eq!(fragment.location(), 4);
+        assert_eq!(remaining.location(), 7);
+
+        let data: &[u8] = b"\x7fELF..";
+        let result: IResult<_, _, LocatedError> = tag_located(&b"\x7fELF"[..])(Located::from(data));
+        let (remaining, (span, fragment)) = result.unwrap();
+        assert_eq!(span, 0..4);
+        assert_eq!(&*fragment, &b"\x7fELF"[..]);
+        assert_eq!(fragment.location(), 0);
+        assert_eq!(&*remaining, &b".."[..]);
+
+        let result: IResult<_, _, LocatedError> = tag_located("let")(Located::new("var x", 4));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn char_located_gets_offset_before_char() {
+        let result: IResult<_, _, LocatedError> = char_located('+')(Located::new("+1", 3));
+        let (remaining, (offset, c)) = result.unwrap();
+        assert_eq!((offset, c), (3, '+'));
+        assert_eq!(remaining.location(), 4);
+
+        let result: IResult<_, _, LocatedError> = char_located('+')(Located::new("-1", 3));
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.location(), 3),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn render_excerpt_underlines_spans() {
+        assert_eq!(render_excerpt("let x = 1;", 4..5), "let x = 1;\n    ^\n");
+        assert_eq!(render_excerpt("\tx = y;", 5..6), "\tx = y;\n\t    ^\n");
+        assert_eq!(render_excerpt("ab\ncd\nef", 1..4), "ab\n ^\ncd\n^\n",);
+        // Zero-width spans get a single caret.
+        assert_eq!(render_excerpt("ab", 2..2), "ab\n  ^\n");
+    }
+
+    #[test]
+    fn render_excerpt_clamps_to_char_boundaries() {
+        // `é` occupies bytes 1..3.
+        assert_eq!(render_excerpt("aéb", 2..4), "aéb\n ^^\n");
+        assert_eq!(render_excerpt("aéb", 0..100), "aéb\n^^^\n");
+    }
+
+    #[test]
+    fn to_lsp_range_counts_utf16_columns() {
+        let lc = |line, column| LineColumn { line, column };
+        // `é` is one UTF-16 unit and `😀` is two.
+        let source = "é😀x\nab";
+        assert_eq!(to_lsp_range(source, 6..7), (lc(0, 3), lc(0, 4)));
+        assert_eq!(to_lsp_range(source, 2..2), (lc(0, 1), lc(0, 1)));
+        assert_eq!(to_lsp_range(source, 7..10), (lc(0, 4), lc(1, 2)));
+    }
+
+    #[test]
+    fn remap_spans_shifts_nested_spans() {
+        let mut tree = Spanned::new(
+            0..6,
+            vec![
+                Spanned::new(0..1, Some(Spanned::new(0..1, 'a'))),
+                Spanned::new(2..6, None),
+            ],
+        );
+        tree.remap(&|span: Range<usize>| (span.start + 10)..(span.end + 10));
+        assert_eq!(tree.span, 10..16);
+        assert_eq!(tree.value[0].span, 10..11);
+        assert_eq!(tree.value[0].value.as_ref().unwrap().span, 10..11);
+        assert_eq!(tree.value[1].span, 12..16);
+    }
+
+    #[test]
+    fn compare_located_ignores_location() {
+        let input = Located::new("abc", 0);
+        assert_eq!(input.compare(Located::new("ab", 7)), CompareResult::Ok);
+        assert_eq!(input.compare(Located::new("ax", 0)), CompareResult::Error);
+        assert_eq!(
+            input.compare(Located::new("abcd", 3)),
+            CompareResult::Incomplete
+        );
+        assert_eq!(
+            input.compare_no_case(Located::new("AB", 7)),
+            CompareResult::Ok
+        );
+    }
+
+    #[test]
+    fn skip_whitespace_span_gets_consumed_span() {
+        let result: IResult<_, _, LocatedError> =
+            skip_whitespace_span(Located::new(" \t\n\r\nx", 2));
+        let (remaining, (span, ())) = result.unwrap();
+        assert_eq!(span, 2..7);
+        assert_eq!(&*remaining, "x");
+
+        let result: IResult<_, _, LocatedError> = skip_whitespace_span(Located::new("x ", 2));
+        let (remaining, (span, ())) = result.unwrap();
+        assert_eq!(span, 2..2);
+        assert_eq!(&*remaining, "x ");
+
+        let result: IResult<_, _, LocatedError> = skip_whitespace_span(Located::new("  ", 0));
+        assert_eq!(result.unwrap().1 .0, 0..2);
+    }
+
+    #[test]
+    fn by_fragment_hashes_fragment() {
+        use std::collections::HashSet;
+
+        let source = "ab ab";
+        let (first, second) = (Located::new(&source[..2], 0), Located::new(&source[3..], 3));
+        let located: HashSet<_> = [first, second].iter().copied().collect();
+        assert_eq!(located.len(), 2);
+        let fragments: HashSet<_> = [first, second].iter().copied().map(ByFragment).collect();
+        assert_eq!(fragments.len(), 1);
+    }
+
+    #[test]
+    fn offset_between_saturates() {
+        let (earlier, later) = (Located::new("abc", 2), Located::new("c", 4));
+        assert_eq!(offset_between(&earlier, &later), 2);
+        assert_eq!(offset_between(&earlier, &earlier), 0);
+        assert_eq!(offset_between(&later, &earlier), 0);
+    }
+
+    #[test]
+    fn char_located_counts_chars() {
+        let input = CharLocated::from("aé😀b".as_bytes());
+        let (remaining, taken) = input.take_split(7);
+        assert_eq!(taken.as_bytes(), "aé😀".as_bytes());
+        assert_eq!((taken.location(), taken.char_location()), (0, 0));
+        assert_eq!((remaining.location(), remaining.char_location()), (7, 3));
+        let remaining = remaining.take_split(1).0;
+        assert_eq!((remaining.location(), remaining.char_location()), (8, 4));
+    }
+
+    #[test]
+    fn by_fragment_borrows_fragment() {
+        use std::collections::HashSet;
+
+        let keywords: HashSet<_> = [Located::new("fn", 0), Located::new("let", 9)]
+            .iter()
+            .copied()
+            .map(ByFragment)
+            .collect();
+        assert!(keywords.contains("let"));
+        assert!(!keywords.contains("var"));
+    }
+
+    #[test]
+    fn locate_gets_span_of_raw_input() {
+        use nom::character::complete::alpha1;
+
+        let result: IResult<_, _, LocatedError> = locate(alpha1)("abc123");
+        let (remaining, (span, output)) = result.unwrap();
+        assert_eq!(remaining, "123");
+        assert_eq!(span, 0..3);
+        assert_eq!(&*output, "abc");
+    }
+
+    #[test]
+    fn map_res_located_spans_rejected_output() {
+        use nom::character::complete::digit1;
+
+        let mut parser = map_res_located(digit1, |digits: Located<str>| digits.parse::<u8>());
+        let result: IResult<_, _, NomError<Located<str>>> = parser(Located::new("256;", 4));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.code, ErrorKind::MapRes);
+                assert_eq!(error.input.location(), 4);
+                assert_eq!(&*error.input, "256");
+            }
+            _ => panic!(),
+        }
+        let result: IResult<_, _, NomError<Located<str>>> = parser(Located::new("255;", 4));
+        assert_eq!(result.unwrap().1, 255);
+    }
+
+    #[test]
+    fn quoted_spanned_spans_quotes() {
+        use nom::bytes::complete::take_while;
+        use nom::character::complete::char;
+
+        let mut parser = quoted_spanned(char('"'), take_while(|c| c != '"'), char('"'));
+        let result: IResult<_, _, LocatedError> = parser(Located::new("\"abc\";", 2));
+        let (remaining, quoted) = result.unwrap();
+        assert_eq!(quoted.span, 2..7);
+        assert_eq!(&*quoted.value, "abc");
+        assert_eq!(&*remaining, ";");
+
+        let result: IResult<_, _, LocatedError> = parser(Located::new("\"\"", 0));
+        let quoted = result.unwrap().1;
+        assert_eq!(quoted.span, 0..2);
+        assert_eq!(&*quoted.value, "");
+    }
+
+    #[test]
+    fn as_ref_forwards_to_fragment() {
+        fn len(text: impl AsRef<str>) -> usize {
+            text.as_ref().len()
+        }
+        fn size(bytes: impl AsRef<[u8]>) -> usize {
+            bytes.as_ref().len()
+        }
+
+        let text = String::from("abc");
+        assert_eq!(len(Located::new(&text, 3)), 3);
+        assert_eq!(len(Stateful::new(Located::new(&text, 3), ())), 3);
+        assert_eq!(size(Located::new("abcd", 0)), 4);
+        assert_eq!(size(Located::new(&b"ab"[..], 0)), 2);
+        assert_eq!(size(Stateful::new(Located::new(&b"ab"[..], 0), ())), 2);
+    }
+
+    #[test]
+    fn max_consumed_spans_excess() {
+        use nom::bytes::complete::take_while;
+
+        let mut parser = max_consumed(4, take_while(|c: char| c.is_alphabetic()));
+        let result: IResult<_, _, LocatedError> = parser(Located::new("abcdef!", 2));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.kind, ErrorKind::TooLarge);
+                assert_eq!(error.span, 6..7);
+            }
+            _ => panic!(),
+        }
+        let result: IResult<_, _, LocatedError> = parser(Located::new("abcd!", 2));
+        assert_eq!(result.unwrap().0.location(), 6);
+
+        // The limit does not fall on a `char` boundary.
+        let mut parser = max_consumed(3, take_while(|c: char| c.is_alphabetic()));
+        let result: IResult<_, _, LocatedError> = parser(Located::new("aéé", 0));
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.span, 3..5),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn max_consumed_stops_runaway_parser() {
+        use nom::bytes::streaming::take;
+        use nom::character::complete::char;
+        use nom::multi::many0;
+        use std::cell::Cell;
+
+        let count = Cell::new(0);
+        let item = |input| {
+            count.set(count.get() + 1);
+            char('a')(input)
+        };
+        let source = "a".repeat(1000);
+        let result: IResult<_, _, LocatedError> =
+            max_consumed(10, many0(item))(Located::new(&*source, 2));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.kind, ErrorKind::TooLarge);
+                assert_eq!(error.span, 12..13);
+            }
+            _ => panic!(),
+        }
+        // The parser is applied to the truncated input only, including a
+        // final application that fails at its end.
+        assert_eq!(count.get(), 12);
+
+        // A streaming parser that needs more input than it is given fails at
+        // the limit.
+        let result: IResult<_, _, LocatedError> =
+            max_consumed(10, take(20usize))(Located::new(&*source, 2));
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.span, 12..13),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn located_position_is_absolute() {
+        let input = Located::new("ab,c", 10).take_split(1).0;
+        let result: IResult<_, _, LocatedError> = located_position(|c| c == ',')(input);
+        let (remaining, position) = result.unwrap();
+        assert_eq!(position, Some(12));
+        assert_eq!(remaining.location(), 11);
+
+        let result: IResult<_, _, LocatedError> = located_position(|c| c == ';')(input);
+        assert_eq!(result.unwrap().1, None);
+    }
+
+    #[test]
+    fn debug_tree_renders_labels_and_spans() {
+        struct Node(&'static str, Vec<Spanned<Node>>);
+
+        impl DebugTree for Node {
+            fn label(&self) -> String {
+                self.0.into()
+            }
+
+            fn children(&self) -> Vec<&dyn DebugTree> {
+                self.1.iter().map(|child| child as &dyn DebugTree).collect()
+            }
+        }
+
+        let tree = Spanned::new(
+            0..5,
+            Node(
+                "add",
+                vec![
+                    Spanned::new(0..1, Node("1", vec![])),
+                    Spanned::new(4..5, Node("2", vec![])),
+                ],
+            ),
+        );
+        assert_eq!(debug_tree(&tree), "add [0..5]\n  1 [0..1]\n  2 [4..5]\n");
+        assert_eq!(debug_tree(&Box::new(Node("leaf", vec![]))), "leaf\n");
+    }
+
+    #[test]
+    fn fragment_eq_ignores_location() {
+        let (left, right) = (Located::new("ab", 0), Located::new("ab", 5));
+        assert!(left.fragment_eq(&right));
+        assert!(left != right);
+        assert!(!left.fragment_eq(&Located::new("ba", 0)));
+        assert!(Stateful::new(left, 1).fragment_eq(&Stateful::new(left, 2)));
+    }
+
+    #[test]
+    fn consumed_len_gets_consumed_bytes() {
+        use nom::bytes::complete::take_while;
+
+        let mut parser = consumed_len(take_while(|c: char| c.is_alphabetic()));
+        let result: IResult<_, _, LocatedError> = parser(Located::new("aé1", 3));
+        let (remaining, (n, output)) = result.unwrap();
+        assert_eq!(n, 3);
+        assert_eq!(&*output, "aé");
+        assert_eq!(&*remaining, "1");
+
+        let result: IResult<_, _, LocatedError> = parser(Located::new("1", 3));
+        assert_eq!(result.unwrap().1 .0, 0);
+    }
+
+    #[test]
+    fn take_while_and_till_span_cover_run() {
+        let result: IResult<_, _, LocatedError> =
+            take_while_span(|c: char| c.is_alphanumeric() || c == '_')(Located::new(
+                "foo_1 = 2",
+                4,
+            ));
+        let (remaining, (span, fragment)) = result.unwrap();
+        assert_eq!(span, 4..9);
+        assert_eq!(span.len(), fragment.len());
+        assert_eq!(&*remaining, " = 2");
+
+        let result: IResult<_, _, LocatedError> =
+            take_while_span(|c: char| c.is_alphabetic())(Located::new("1", 4));
+        assert_eq!(result.unwrap().1 .0, 4..4);
+
+        let result: IResult<_, _, LocatedError> =
+            take_till_span(|c| c == '=')(Located::new("foo = 2", 4));
+        let (span, fragment) = result.unwrap().1;
+        assert_eq!(span, 4..8);
+        assert_eq!(&*fragment, "foo ");
+    }
+
+    #[test]
+    fn fold_many0_span_merges_element_spans() {
+        use nom::character::complete::{char, digit1};
+        use nom::sequence::terminated;
+
+        let mut parser = fold_many0_span(terminated(digit1, char(',')), || 0, |n, _| n + 1);
+        let result: IResult<_, _, LocatedError> = parser(Located::new("x", 2));
+        assert_eq!(result.unwrap().1, (2..2, 0));
+        let result: IResult<_, _, LocatedError> = parser(Located::new("12,x", 2));
+        assert_eq!(result.unwrap().1, (2..5, 1));
+        let result: IResult<_, _, LocatedError> = parser(Located::new("1,23,4,x", 2));
+        assert_eq!(result.unwrap().1, (2..9, 3));
+
+        assert_eq!(merge_spans(3..4, 1..2), 1..4);
+    }
+
+    #[test]
+    fn location_through_reference() {
+        fn location(input: &impl Location) -> usize {
+            input.location()
+        }
+
+        let input = Located::new("a", 7);
+        assert_eq!(location(&input), 7);
+        assert_eq!(location(&&input), 7);
+    }
+
+    #[test]
+    fn located_lines_excludes_terminators() {
+        let source = "ab\r\ncd\n\nef";
+        let lines: Vec<_> = located_lines(source).collect();
+        assert_eq!(
+            lines,
+            [(0..2, "ab"), (4..6, "cd"), (7..7, ""), (8..10, "ef")]
+        );
+        for (span, line) in lines {
+            assert_eq!(&source[span], line);
+        }
+
+        let lines: Vec<_> = located_lines("ab\n").collect();
+        assert_eq!(lines, [(0..2, "ab")]);
+        assert_eq!(located_lines("").count(), 0);
+    }
+
+    #[test]
+    fn contextual_shares_config() {
+        type Input<'i> = Contextual<Located<'i, str>, usize, usize>;
+
+        fn chunk(input: Input) -> IResult<Input, Located<str>, LocatedError> {
+            if input.input_len() == 0 {
+                return Err(ErrorMode::Error(LocatedError::new(
+                    input.location()..input.location(),
+                    ErrorKind::Eof,
+                )));
+            }
+            let n = (*input.config()).min(input.input_len());
+            let (mut remaining, taken) = input.take_split(n);
+            *remaining.state_mut() += 1;
+            Ok((remaining, taken.data))
+        }
+
+        let config = Rc::new(2);
+        let input: Input = Stateful::new(Located::from("abcde"), Context::new(config.clone(), 0));
+        let (remaining, chunks) = nom::multi::many1(chunk)(input).unwrap();
+        let chunks: Vec<_> = chunks.iter().map(|chunk| &**chunk).collect();
+        assert_eq!(chunks, ["ab", "cd", "e"]);
+        assert_eq!(remaining.state.state, 3);
+        assert!(Rc::ptr_eq(&config, &remaining.state.shared_config(),));
+    }
+
+    #[test]
+    fn map_with_location_gets_start_location() {
+        use nom::character::complete::alpha1;
+
+        let mut parser =
+            alpha1.map_with_location(|location, word: Located<str>| (location, word.len()));
+        let result: IResult<_, _, LocatedError> = parser.parse(Located::new("abc1", 6));
+        let (remaining, output) = result.unwrap();
+        assert_eq!(output, (6, 3));
+        assert_eq!(remaining.location(), 9);
+    }
+
+    #[test]
+    fn as_located_ref_borrows_fragment() {
+        let text = String::from("abc");
+        let input = Located::new(&text, 3);
+        let view: Located<str> = input.as_located_ref();
+        assert_eq!(view.location(), 3);
+        assert_eq!(&*view, "abc");
+        assert_eq!(view.as_ptr(), text.as_ptr());
+
+        let bytes = vec![1u8, 2];
+        let input = Stateful::new(Located::new(&bytes, 1), 'x');
+        let view: Stateful<Located<[u8]>, char> = input.as_located_ref();
+        assert_eq!(view.location(), 1);
+        assert_eq!(view.state, 'x');
+        assert_eq!(view.data.as_ptr(), bytes.as_ptr());
+    }
+
+    #[test]
+    fn tracked_gets_full_location() {
+        let input = Tracked::from("ab\ncd\n\nef");
+        assert_eq!(input.full_location(), (0, 0, 0));
+        let (input, _) = input.take_split(4);
+        assert_eq!(input.full_location(), (4, 1, 1));
+        let (input, _) = input.take_split(3);
+        assert_eq!(input.full_location(), (7, 3, 0));
+        let (input, _) = input.take_split(2);
+        assert_eq!(input.full_location(), (9, 3, 2));
+        assert_eq!(input.line_column(), LineColumn { line: 3, column: 2 });
+    }
+
+    #[test]
+    fn all_consuming_span_spans_trailing_input() {
+        use nom::character::complete::alpha1;
+
+        let result: IResult<_, _, LocatedError> = all_consuming_span(alpha1)(Located::new("ab", 2));
+        assert_eq!(&*result.unwrap().1, "ab");
+
+        let result: IResult<_, _, LocatedError> =
+            all_consuming_span(alpha1)(Located::new("ab!?", 2));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.location(), 4);
+                assert_eq!(error.span, 4..6);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn try_slice_to_rejects_foreign_fragments() {
+        let source = "abcdef";
+        let input = Located::new(source, 10);
+        let fragment = input.try_slice_to(&source[2..4]).unwrap();
+        assert_eq!(fragment.location(), 12);
+        assert_eq!(&*fragment, "cd");
+        assert_eq!(input.try_slice_to(&source[6..]).unwrap().location(), 16);
+
+        let other = String::from("cd");
+        assert!(input.try_slice_to(&other).is_none());
+        assert!(Located::new(&source[2..], 12)
+            .try_slice_to(&source[..2])
+            .is_none());
+    }
+
+    #[cfg(feature = "logos")]
+    #[test]
+    fn lex_spanned_gets_token_spans() {
+        use logos::Logos;
+
+        #[derive(Clone, Copy, Debug, Eq, Logos, PartialEq)]
+        #[logos(skip r"[ ]+")]
+        enum Token {
+            #[token("+")]
+            Plus,
+            #[regex("[0-9]+")]
+            Number,
+        }
+
+        let tokens = lex_spanned(Token::lexer("12 + 3")).unwrap();
+        assert_eq!(
+            tokens,
+            [
+                Spanned::new(0..2, Token::Number),
+                Spanned::new(3..4, Token::Plus),
+                Spanned::new(5..6, Token::Number),
+            ],
+        );
+        let input = LocatedTokens::new(&tokens);
+        assert_eq!(input.take_split(2).0.location(), 5);
+
+        let error = lex_spanned(Token::lexer("1 ? 2")).unwrap_err();
+        assert_eq!(error.span, 2..3);
+    }
+
+    #[test]
+    fn located_tokens_are_located_by_source() {
+        let tokens = [
+            Spanned::new(0..3, "let"),
+            Spanned::new(4..5, "x"),
+            Spanned::new(6..7, "="),
+        ];
+        let input = LocatedTokens::new(&tokens);
+        assert_eq!(input.location(), 0);
+        let (remaining, taken) = input.take_split(1);
+        assert_eq!(taken.location(), 0);
+        assert_eq!(remaining.location(), 4);
+        assert_eq!(input.offset(&remaining), 1);
+        let (remaining, _) = remaining.take_split(2);
+        assert_eq!(remaining.input_len(), 0);
+        assert_eq!(remaining.location(), 7);
+    }
+
+    #[test]
+    fn stateful_fragment_eq_ignores_state() {
+        let (left, right) = (Stateful::new("ab", 1), Stateful::new("ab", 2));
+        assert!(left.fragment_eq(&right));
+        assert!(left != right);
+        assert!(!left.fragment_eq(&Stateful::new("ba", 1)));
+    }
+
+    #[test]
+    fn span_line_col_gets_endpoints() {
+        use nom::bytes::complete::take;
+
+        let source = "ab\ncd";
+        let index = LineIndex::new(source);
+        let input = Located::from(source).take_split(1).0;
+        let result: IResult<_, _, LocatedError> = span_line_col(&index, take(3usize))(input);
+        let (start, end, output) = result.unwrap().1;
+        assert_eq!(start, LineColumn { line: 0, column: 1 });
+        assert_eq!(end, LineColumn { line: 1, column: 1 });
+        assert_eq!(&*output, "b\nc");
+    }
+
+    #[test]
+    fn cut_at_records_commit_location() {
+        use nom::character::complete::char;
+        use nom::sequence::preceded;
+
+        let mut parser = preceded(char('{'), cut_at("block", char('}')));
+        let result: IResult<_, _, LocatedError> = parser(Located::new("{x", 5));
+        match result {
+            Err(ErrorMode::Failure(error)) => {
+                assert_eq!(error.location(), 6);
+                assert_eq!(error.context, [(6, "block")]);
+            }
+            _ => panic!(),
+        }
+        let result: IResult<_, _, LocatedError> = parser(Located::new("{}", 5));
+        assert_eq!(result.unwrap().1, '}');
+    }
+
+    #[test]
+    fn spanned_display_omits_span() {
+        let spanned = Spanned::new(1..4, "abc");
+        assert_eq!(format!("{}", spanned), "abc");
+        assert_eq!(
+            format!("{:?}", spanned),
+            r#"Spanned { span: 1..4, value: "abc" }"#,
+        );
+    }
+
+    #[test]
+    fn separated_pair_span_covers_pair() {
+        use nom::character::complete::{alpha1, char, digit1, space0};
+        use nom::sequence::delimited;
+
+        let mut parser = separated_pair_span(alpha1, delimited(space0, char('='), space0), digit1);
+        let result: IResult<_, _, LocatedError> = parser(Located::new("x = 12;", 3));
+        let (remaining, (span, (key, value))) = result.unwrap();
+        assert_eq!(span, 3..9);
+        assert_eq!((&*key, &*value), ("x", "12"));
+        assert_eq!(&*remaining, ";");
+    }
+
+    #[test]
+    fn trivia_gets_kinds_and_spans() {
+        use nom::multi::many0;
+
+        let result: IResult<_, _, LocatedError> =
+            many0(trivia("//"))(Located::from("  // a\n\t//\nx"));
+        let (remaining, trivia) = result.unwrap();
+        assert_eq!(
+            trivia,
+            [
+                Spanned::new(0..2, Trivia::Whitespace),
+                Spanned::new(2..6, Trivia::LineComment),
+                Spanned::new(6..8, Trivia::Whitespace),
+                Spanned::new(8..10, Trivia::LineComment),
+                Spanned::new(10..11, Trivia::Whitespace),
+            ],
+        );
+        assert_eq!(&*remaining, "x");
+    }
+
+    #[test]
+    fn progress_reports_multiples_of_step() {
+        use nom::bytes::complete::take;
+        use nom::multi::many0;
+
+        let mut locations = Vec::new();
+        {
+            let mut parser = many0(progress(
+                4,
+                |location| locations.push(location),
+                take(3usize),
+            ));
+            let result: IResult<_, _, LocatedError> = parser(Located::from("abcdefghijkl"));
+            assert_eq!(result.unwrap().1.len(), 4);
+        }
+        assert_eq!(locations, [4, 8, 12]);
+    }
+
+    #[test]
+    fn with_rest_span_shrinks() {
+        use nom::bytes::complete::take;
+
+        let mut parser = with_rest_span(take(2usize));
+        let result: IResult<_, _, LocatedError> = parser(Located::new("abcde", 1));
+        let (remaining, (_, rest)) = result.unwrap();
+        assert_eq!(rest, 3..6);
+        let result: IResult<_, _, LocatedError> = parser(remaining);
+        assert_eq!(result.unwrap().1 .1, 5..6);
+    }
+
+    #[test]
+    fn bit_located_spans_bits() {
+        use nom::bits::complete::take;
+        use nom::sequence::tuple;
+
+        let data: &[u8] = &[0b1010_1100, 0b0101_0000];
+        let mut parser = bits_located(tuple((
+            span(bit_located(take::<_, u8, _, _>(3usize))),
+            span(bit_located(take::<_, u8, _, _>(7usize))),
+        )));
+        let result: IResult<_, _, LocatedError> = parser(Located::new(data, 1));
+        let (remaining, (((start, n), first), ((second_start, m), second))) = result.unwrap();
+        assert_eq!(((start, n), first), ((8, 3), 0b101));
+        assert_eq!(((second_start, m), second), ((11, 7), 0b011_0001));
+        assert_eq!(remaining.location(), 3);
+    }
+
+    #[test]
+    fn newline_aware_tracks_lines_and_columns() {
+        use nom::bytes::complete::take;
+
+        let input = Stateful::new(
+            Located::from("ab\ncd\ne"),
+            LineColumn { line: 0, column: 0 },
+        );
+        let result: IResult<_, _, LocatedError> = newline_aware(take(4usize))(input);
+        let (remaining, _) = result.unwrap();
+        assert_eq!((remaining.line(), remaining.column()), (1, 1));
+        let result: IResult<_, _, LocatedError> = newline_aware(take(3usize))(remaining);
+        let (remaining, _) = result.unwrap();
+        assert_eq!((remaining.line(), remaining.column()), (2, 1));
+    }
+
+    #[test]
+    fn located_into_span() {
+        assert_eq!(Range::from(Located::new("abc", 4)), 4..7);
+        assert_eq!(Range::from(Located::new("", 4)), 4..4);
+    }
+
+    #[test]
+    fn assert_spans_ordered_finds_first_pair() {
+        let spanned = |span| Spanned::new(span, ());
+        assert_eq!(
+            assert_spans_ordered(&[spanned(0..1), spanned(1..3), spanned(5..5)]),
+            Ok(())
+        );
+        assert_eq!(assert_spans_ordered::<()>(&[]), Ok(()));
+        assert_eq!(
+            assert_spans_ordered(&[spanned(0..1), spanned(4..5), spanned(2..3)]),
+            Err((1, 2))
+        );
+        assert_eq!(
+            assert_spans_ordered(&[spanned(0..2), spanned(1..3)]),
+            Err((0, 1))
+        );
+    }
+
+    #[test]
+    fn peek_span_consumes_nothing() {
+        use nom::character::complete::alpha1;
+
+        let input = Located::new("abc1", 2);
+        let result: IResult<_, _, LocatedError> = peek_span(alpha1)(input);
+        let (remaining, (span, output)) = result.unwrap();
+        assert_eq!(span, 2..5);
+        assert_eq!(&*output, "abc");
+        assert_eq!(remaining, input);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn located_path_bytes() {
+        let path = Path::new("/usr/lib/x");
+        let input: Located<[u8]> = Located::from(path);
+        let separators: Vec<_> = input
+            .iter_indices()
+            .filter(|(_, byte)| *byte == b'/')
+            .map(|(n, _)| input.take_split(n).0.location())
+            .collect();
+        assert_eq!(separators, [0, 4, 8]);
+        let input: Located<[u8]> = Located::from(OsStr::new("ab"));
+        assert_eq!(input.as_bytes(), b"ab");
+    }
+
+    #[test]
+    fn parse_failure_from_located_error() {
+        let mut error = LocatedError::new(3..5, ErrorKind::Tag);
+        error.context.push((1, "item"));
+        let failure = ParseFailure::from(error);
+        assert_eq!(failure.span, 3..5);
+        assert_eq!(failure.to_string(), "Tag, in item at 1 at 3..5");
+        let error: Box<dyn Error> = Box::new(failure);
+        assert_eq!(error.to_string(), "Tag, in item at 1 at 3..5");
+    }
+
+    #[test]
+    fn value_spanned_spans_literal() {
+        use nom::branch::alt;
+        use nom::bytes::complete::tag;
+
+        #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+        enum Keyword {
+            Let,
+            Fn,
+        }
+
+        let mut parser = alt((
+            value_spanned(Keyword::Let, tag("let")),
+            value_spanned(Keyword::Fn, tag("fn")),
+        ));
+        let result: IResult<_, _, LocatedError> = parser(Located::new("fn f", 4));
+        let (remaining, keyword) = result.unwrap();
+        assert_eq!(keyword, Spanned::new(4..6, Keyword::Fn));
+        assert_eq!(remaining.location(), 6);
+    }
+
+    #[test]
+    fn coverage_gets_uncovered_ranges() {
+        assert_eq!(coverage(5, &[0..2, 2..5]), []);
+        assert_eq!(coverage(10, &[7..9, 0..3, 2..5]), [5..7, 9..10]);
+        assert_eq!(coverage(6, &[1..1, 2..3, 4..4]), [0..2, 3..6]);
+        assert_eq!(coverage(6, &[1..2, 4..9]), [0..1, 2..4]);
+        assert_eq!(coverage(0, &[1..2, 3..4]), []);
+        assert_eq!(coverage(0, &[]), []);
+    }
+
+    #[test]
+    fn rest_located_gets_remainder() {
+        let input = Located::new("abc", 4).take_split(1).0;
+        let result: IResult<_, _, LocatedError> = rest_located(input);
+        let (remaining, rest) = result.unwrap();
+        assert_eq!(&*rest, "bc");
+        assert_eq!(rest.location(), 5);
+        assert_eq!(remaining.input_len(), 0);
+        assert_eq!(remaining.location(), 7);
+    }
+
+    #[test]
+    fn unique_key_reports_both_locations() {
+        use nom::character::complete::{alpha1, char};
+        use nom::combinator::opt;
+        use nom::sequence::terminated;
+
+        let mut parser = terminated(unique_key(alpha1), opt(char(',')));
+        let input = Stateful::new(Located::from("a,b,a"), HashMap::new());
+        let result: IResult<_, _, ExternalError<DuplicateKey>> = parser(input);
+        let (input, _) = result.unwrap();
+        let result: IResult<_, _, ExternalError<DuplicateKey>> = parser(input);
+        let (input, _) = result.unwrap();
+        assert_eq!(input.state.len(), 2);
+        let result: IResult<_, _, ExternalError<DuplicateKey>> = parser(input);
+        match result {
+            Err(ErrorMode::Error(ExternalError(Some(error)))) => {
+                assert_eq!(
+                    error,
+                    DuplicateKey {
+                        key: "a".into(),
+                        original: 0,
+                        duplicate: 4,
+                    },
+                );
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn located_rposition_is_absolute() {
+        let input = Located::new("a.b.c", 10).take_split(1).0;
+        let result: IResult<_, _, LocatedError> = located_rposition(|c| c == '.')(input);
+        let (remaining, position) = result.unwrap();
+        assert_eq!(position, Some(13));
+        assert_eq!(remaining, input);
+
+        let result: IResult<_, _, LocatedError> = located_rposition(|c| c == ';')(input);
+        assert_eq!(result.unwrap().1, None);
+    }
+
+    #[test]
+    fn tagged_alt_gets_branch_and_span() {
+        use nom::bytes::complete::tag;
+        use nom::character::complete::alpha1;
+
+        let mut parser = tagged_alt((tag("let"), tag("fn"), alpha1));
+        let mut parse = |text: &'static str| {
+            let result: IResult<_, _, LocatedError> = parser(Located::new(text, 1));
+            result.map(|(_, (n, span, output))| (n, span, output.into_data()))
+        };
+        assert_eq!(parse("let x").unwrap(), (0, 1..4, "let"));
+        assert_eq!(parse("fn f").unwrap(), (1, 1..3, "fn"));
+        assert_eq!(parse("loop").unwrap(), (2, 1..5, "loop"));
+        assert!(parse("1").is_err());
+    }
+
+    #[test]
+    fn integer_spanned_spans_literal() {
+        let result: IResult<_, Spanned<u8>, LocatedError> =
+            integer_spanned(10)(Located::new("255;", 2));
+        let (remaining, value) = result.unwrap();
+        assert_eq!(value, Spanned::new(2..5, 255));
+        assert_eq!(&*remaining, ";");
+
+        let result: IResult<_, Spanned<u8>, LocatedError> =
+            integer_spanned(10)(Located::new("256;", 2));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.kind, ErrorKind::TooLarge);
+                assert_eq!(error.span, 2..5);
+            }
+            _ => panic!(),
+        }
+
+        let result: IResult<_, Spanned<i8>, LocatedError> =
+            integer_spanned(16)(Located::from("-80"));
+        assert_eq!(result.unwrap().1, Spanned::new(0..3, i8::MIN));
+        let result: IResult<_, Spanned<u128>, LocatedError> =
+            integer_spanned(10)(Located::from("340282366920938463463374607431768211455"));
+        assert_eq!(result.unwrap().1.value, u128::MAX);
+        let result: IResult<_, Spanned<i128>, LocatedError> =
+            integer_spanned(10)(Located::from("-170141183460469231731687303715884105728"));
+        assert_eq!(result.unwrap().1.value, i128::MIN);
+
+        let result: IResult<_, Spanned<u8>, LocatedError> =
+            integer_spanned(10)(Located::new("-5;", 2));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.kind, ErrorKind::Verify);
+                assert_eq!(error.span, 2..3);
+            }
+            _ => panic!(),
+        }
+        let result: IResult<_, Spanned<u8>, LocatedError> =
+            integer_spanned(10)(Located::new("-999", 2));
+        assert!(matches!(result, Err(ErrorMode::Error(error)) if error.kind == ErrorKind::Verify));
+        let result: IResult<_, Spanned<u8>, LocatedError> =
+            integer_spanned(10)(Located::from("-0"));
+        assert_eq!(result.unwrap().1, Spanned::new(0..2, 0));
+
+        let result: IResult<_, Spanned<u8>, LocatedError> = integer_spanned(10)(Located::from(""));
+        assert!(result.is_err());
+        let result: IResult<_, Spanned<u8>, LocatedError> =
+            integer_spanned(10)(Located::from("-x"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn integer_spanned_rejects_radix() {
+        let _ = integer_spanned::<u8, Located<str>, LocatedError>(37);
+    }
+
+    #[test]
+    fn many_bounded_locates_excess() {
+        use nom::character::complete::{char, digit1};
+        use nom::sequence::terminated;
+
+        let mut parser = many_bounded(1, 2, terminated(digit1, char(',')));
+        let result: IResult<_, _, LocatedError> = parser(Located::from("1,22,x"));
+        let (remaining, outputs) = result.unwrap();
+        assert_eq!(outputs.len(), 2);
+        assert_eq!(&*remaining, "x");
+
+        let result: IResult<_, _, LocatedError> = parser(Located::from("1,22,3,"));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.kind, ErrorKind::TooLarge);
+                assert_eq!(error.location(), 5);
+            }
+            _ => panic!(),
+        }
+        let result: IResult<_, _, LocatedError> = parser(Located::from("x"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn between_span_gets_gap() {
+        use nom::character::complete::{alpha1, digit1, space0};
+
+        let mut parser = between_span(alpha1, space0, digit1);
+        let result: IResult<_, _, LocatedError> = parser(Located::new("ab  12", 1));
+        let (first, gap, second) = result.unwrap().1;
+        assert_eq!((&*first, gap, &*second), ("ab", 3..5, "12"));
+        let result: IResult<_, _, LocatedError> = parser(Located::new("ab12", 1));
+        assert_eq!(result.unwrap().1 .1, 3..3);
+    }
+
+    #[test]
+    fn until_sentinel_span_excludes_sentinel() {
+        let data: &[u8] = b"ab\0c";
+        let result: IResult<_, _, LocatedError> = until_sentinel_span(0)(Located::new(data, 2));
+        let (remaining, (span, content)) = result.unwrap();
+        assert_eq!(span, 2..4);
+        assert_eq!(&*content, b"ab");
+        assert_eq!(remaining.location(), 5);
+        assert_eq!(&*remaining, b"c");
+
+        let data: &[u8] = b"\0c";
+        let result: IResult<_, _, LocatedError> = until_sentinel_span(0)(Located::new(data, 2));
+        let (remaining, (span, content)) = result.unwrap();
+        assert_eq!(span, 2..2);
+        assert!(content.is_empty());
+        assert_eq!(remaining.location(), 3);
+
+        let data: &[u8] = b"ab";
+        let result: IResult<_, _, LocatedError> = until_sentinel_span(0)(Located::new(data, 2));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn state_diff_observes_state() {
+        use nom::character::complete::anychar;
+
+        type Input<'i> = Stateful<Located<'i, str>, usize>;
+
+        fn count(input: Input) -> IResult<Input, char, LocatedError> {
+            let (mut remaining, c) = anychar(input)?;
+            remaining.state += 1;
+            Ok((remaining, c))
+        }
+
+        let mut parser = state_diff(count, |before: &usize, after: &usize| after - before);
+        let (remaining, output) = parser(Stateful::new(Located::from("ab"), 5)).unwrap();
+        assert_eq!(output, ('a', 1));
+        assert_eq!(remaining.state, 6);
+    }
+
+    #[cfg(feature = "codegen")]
+    #[test]
+    fn span_map_maps_offsets() {
+        let mut map = SpanMap::new();
+        map.insert(10..20, 100..130);
+        map.insert(12..14, 200..204);
+        map.insert(0..5, 50..52);
+        assert_eq!(map.to_generated(15..18), Some(105..108));
+        assert_eq!(map.to_generated(12..14), Some(200..202));
+        assert_eq!(map.to_generated(1..4), Some(51..52));
+        assert_eq!(map.to_generated(30..31), None);
+        assert_eq!(map.to_source(105..108), Some(15..18));
+        assert_eq!(map.to_source(201..202), Some(13..14));
+    }
+
+    #[test]
+    fn strip_bom_keeps_file_offsets() {
+        let input = strip_bom("\u{feff}ab");
+        assert_eq!(&*input, "ab");
+        assert_eq!(input.location(), 3);
+        let input = strip_bom("ab");
+        assert_eq!(&*input, "ab");
+        assert_eq!(input.location(), 0);
+    }
+
+    #[test]
+    fn start_column_gets_column_of_match() {
+        use nom::character::complete::{alpha1, multispace0};
+        use nom::sequence::preceded;
+
+        let source = "ab\n  cd";
+        let index = LineIndex::new(source);
+        let result: IResult<_, _, LocatedError> =
+            start_column(&index, alpha1)(Located::from(source));
+        assert_eq!(result.unwrap().1 .0, 0);
+        let input = Located::from(source).take_split(2).0;
+        let result: IResult<_, _, LocatedError> =
+            preceded(multispace0, start_column(&index, alpha1))(input);
+        let (column, word) = result.unwrap().1;
+        assert_eq!((column, &*word), (2, "cd"));
+    }
+
+    #[test]
+    fn take_while1_and_till1_span_require_input() {
+        let result: IResult<_, _, LocatedError> =
+            take_while1_span(|c: char| c.is_alphabetic())(Located::new("ab1", 3));
+        let (span, fragment) = result.unwrap().1;
+        assert_eq!((span, &*fragment), (3..5, "ab"));
+        let result: IResult<_, _, LocatedError> =
+            take_while1_span(|c: char| c.is_alphabetic())(Located::new("1", 3));
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.location(), 3),
+            _ => panic!(),
+        }
+
+        let result: IResult<_, _, LocatedError> =
+            take_till1_span(|c| c == ';')(Located::new("ab;", 3));
+        assert_eq!(result.unwrap().1 .0, 3..5);
+        let result: IResult<_, _, LocatedError> =
+            take_till1_span(|c| c == ';')(Located::new(";", 3));
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.location(), 3),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn fixed_width_span_counts_items() {
+        let result: IResult<_, _, LocatedError> = fixed_width_span(3)(Located::new("aéb!", 2));
+        let (remaining, (span, field)) = result.unwrap();
+        assert_eq!((span, &*field), (2..6, "aéb"));
+        assert_eq!(&*remaining, "!");
+
+        let data: &[u8] = b"abc";
+        let result: IResult<_, _, LocatedError> = fixed_width_span(3)(Located::new(data, 2));
+        assert_eq!(result.unwrap().1 .0, 2..5);
+
+        let result: IResult<_, _, LocatedError> = fixed_width_span(3)(Located::new("ab", 2));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.kind, ErrorKind::Eof);
+                assert_eq!(error.span, 2..4);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn record_captures_named_spans() {
+        use nom::character::complete::{alpha1, char, digit1};
+
+        let mut parser = record!(name => alpha1, separator => char('='), value => digit1);
+        let input = Stateful::new(Located::from("key=12"), SpanCollector::new());
+        let result: IResult<_, _, LocatedError> = parser(input);
+        let (remaining, _) = result.unwrap();
+        assert_eq!(remaining.state.get("name"), Some(0..3));
+        assert_eq!(remaining.state.get("value"), Some(4..6));
+        assert_eq!(remaining.state.get("other"), None);
+    }
+
+    #[test]
+    fn relative_to_offsets_span() {
+        use nom::character::complete::alpha1;
+
+        let result: IResult<_, _, LocatedError> = relative_to(10, alpha1)(Located::new("ab", 10));
+        assert_eq!(result.unwrap().1 .0, 0..2);
+        let result: IResult<_, _, LocatedError> = relative_to(10, alpha1)(Located::new("ab", 13));
+        assert_eq!(result.unwrap().1 .0, 3..5);
+        let result: IResult<_, _, LocatedError> = relative_to(10, alpha1)(Located::new("ab", 7));
+        assert_eq!(result.unwrap().1 .0, 0..0);
+    }
+
+    #[test]
+    fn detect_mixed_line_endings_reports_minority() {
+        assert_eq!(detect_mixed_line_endings("a\r\nb\r\n"), []);
+        assert_eq!(
+            detect_mixed_line_endings("a\nb\r\nc\nd\re"),
+            [(3..5, LineEnding::CrLf), (8..9, LineEnding::Cr)],
+        );
+        // Ties are broken by the first line ending.
+        assert_eq!(
+            detect_mixed_line_endings("a\r\nb\n"),
+            [(4..5, LineEnding::Lf)]
+        );
+    }
+
+    #[test]
+    fn arena_alloc_resolves_handles() {
+        use nom::character::complete::{alpha1, space0};
+        use nom::combinator::map;
+        use nom::multi::many0;
+        use nom::sequence::terminated;
+
+        let arena = RefCell::new(SpanArena::new());
+        let handles = {
+            let mut parser = many0(terminated(
+                arena_alloc(
+                    &arena,
+                    map(alpha1, |word: Located<str>| word.into_data().to_owned()),
+                ),
+                space0,
+            ));
+            let result: IResult<_, _, LocatedError> = parser(Located::from("ab cde f"));
+            result.unwrap().1
+        };
+        let arena = arena.into_inner();
+        assert_eq!(arena.len(), 3);
+        assert_eq!(
+            arena.get(handles[0]),
+            Some(&Spanned::new(0..2, "ab".to_owned()))
+        );
+        assert_eq!(
+            arena.get(handles[1]),
+            Some(&Spanned::new(3..6, "cde".to_owned()))
+        );
+        assert_eq!(
+            arena.get(handles[2]),
+            Some(&Spanned::new(7..8, "f".to_owned()))
+        );
+    }
+
+    #[test]
+    fn alt_expected_aggregates_labels() {
+        use nom::bytes::complete::tag;
+        use nom::character::complete::digit1;
+
+        let mut parser = alt_expected((("let", tag("let")), ("fn", tag("fn")), ("number", digit1)));
+        let result: IResult<_, _, ExternalError<Expected>> = parser(Located::new("12", 4));
+        assert_eq!(&*result.unwrap().1, "12");
+        let result: IResult<_, _, ExternalError<Expected>> = parser(Located::new("x", 4));
+        match result {
+            Err(ErrorMode::Error(ExternalError(Some(expected)))) => {
+                assert_eq!(expected.location, 4);
+                assert_eq!(expected.labels, ["let", "fn", "number"]);
+                assert_eq!(expected.to_string(), "expected one of let, fn, number at 4");
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn interpolated_rebases_fragment() {
+        use nom::bytes::complete::tag;
+        use nom::character::complete::alpha1;
+        use nom::sequence::separated_pair;
+
+        let source = r#""a${x + y}b""#;
+        let outer = Located::new(source, 10);
+        let result: IResult<_, _, LocatedError> = interpolated(
+            &outer,
+            &source[4..9],
+            separated_pair(alpha1, tag(" + "), alpha1),
+        );
+        let (remaining, expression) = result.unwrap();
+        let (left, right) = expression.value;
+        assert_eq!(expression.span, 14..19);
+        assert_eq!((left.location(), right.location()), (14, 18));
+        assert_eq!(&source[(right.location() - 10)..][..1], "y");
+        assert_eq!(remaining.location(), 19);
+
+        let other = String::from("x + y");
+        let result: IResult<_, _, LocatedError> =
+            interpolated(&outer, &other, separated_pair(alpha1, tag(" + "), alpha1));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.kind, ErrorKind::Verify);
+                assert_eq!(error.location(), 10);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn kv_map_records_duplicate_spans() {
+        use nom::character::complete::{alpha1, char, digit1};
+        use nom::combinator::opt;
+        use nom::multi::many0;
+        use nom::sequence::{preceded, terminated};
+
+        type Input<'i> = Stateful<Located<'i, str>, KeyValues<&'i str, &'i str>>;
+
+        fn key<'i>(input: Input<'i>) -> IResult<Input<'i>, &'i str, LocatedError> {
+            alpha1(input).map(|(remaining, key)| (remaining, key.data.into_data()))
+        }
+
+        fn value<'i>(input: Input<'i>) -> IResult<Input<'i>, &'i str, LocatedError> {
+            preceded(char('='), digit1)(input)
+                .map(|(remaining, value)| (remaining, value.data.into_data()))
+        }
+
+        let mut parser = many0(terminated(kv_map(key, value), opt(char(';'))));
+        let (remaining, _) =
+            parser(Stateful::new(Located::from("a=1;b=22"), KeyValues::new())).unwrap();
+        assert_eq!(remaining.state.entries["b"], Spanned::new(5..8, "22"));
+        assert!(remaining.state.duplicates.is_empty());
+
+        let (remaining, _) = parser(Stateful::new(
+            Located::from("a=1;b=2;a=3"),
+            KeyValues::new(),
+        ))
+        .unwrap();
+        let state = remaining.state;
+        assert_eq!(state.entries["a"], Spanned::new(1..3, "1"));
+        assert_eq!(
+            state.duplicates,
+            [DuplicateEntry {
+                original: 0..1,
+                duplicate: 8..9,
+            }],
+        );
+        assert_eq!(state.key_span(&"a"), Some(0..1));
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn grapheme_column_counts_clusters() {
+        // `e` and a combining acute accent form one cluster.
+        let source = "e\u{301}x";
+        assert_eq!(grapheme_column(source, 3), 1);
+        assert_eq!(grapheme_column(source, 1), 0);
+        assert_eq!(grapheme_column(source, 4), 2);
+        // Regional indicators pair into a single flag.
+        let source = "\u{1f1fa}\u{1f1f8}x";
+        assert_eq!(grapheme_column(source, 8), 1);
+        assert_eq!(grapheme_column(source, 9), 2);
+        let source = "ab\r\nc";
+        assert_eq!(grapheme_column(source, 2), 2);
+        assert_eq!(grapheme_column(source, 3), 2);
+        assert_eq!(grapheme_column(source, 4), 0);
+        assert_eq!(grapheme_column(source, 5), 1);
+    }
+
+    #[test]
+    fn warn_span_records_warnings() {
+        use nom::branch::alt;
+        use nom::bytes::complete::tag;
+
+        let mut parser = alt((warn_span("deprecated", tag("var")), tag("let")));
+        let result: IResult<_, _, LocatedError> =
+            parser(Stateful::new(Located::new("var x", 2), Vec::new()));
+        let (remaining, _) = result.unwrap();
+        assert_eq!(remaining.state, [Spanned::new(2..5, "deprecated")]);
+        let result: IResult<_, _, LocatedError> =
+            parser(Stateful::new(Located::new("let x", 2), Vec::new()));
+        assert!(result.unwrap().0.state.is_empty());
+    }
+
+    #[test]
+    fn metered_counts_revisits() {
+        use nom::branch::alt;
+        use nom::bytes::complete::tag;
+        use nom::sequence::pair;
+
+        let metrics = RefCell::new(Metrics::new());
+        {
+            let mut parser = alt((
+                pair(metered(&metrics, tag("a")), tag("b")),
+                pair(metered(&metrics, tag("a")), tag("c")),
+                pair(metered(&metrics, tag("a")), tag("d")),
+            ));
+            let result: IResult<_, _, LocatedError> = parser(Located::new("ad", 3));
+            assert!(result.is_ok());
+        }
+        let metrics = metrics.into_inner();
+        assert_eq!(metrics.visits(3), 3);
+        assert_eq!(metrics.revisits(3), 2);
+        assert_eq!(metrics.revisits(4), 0);
+        assert_eq!(metrics.hot_locations(), [(3, 2)]);
+        assert_eq!(metrics.furthest(), Some(4));
+    }
+
+    #[test]
+    fn missing_token_span_is_zero_width() {
+        let input = Located::new("}", 7);
+        let result: IResult<_, _, LocatedError> = missing_token_span(";")(input);
+        let (remaining, missing) = result.unwrap();
+        assert_eq!(missing, Spanned::new(7..7, Missing { label: ";" }));
+        assert_eq!(remaining, input);
+    }
+
+    #[test]
+    fn bounded_token_spans_overlong_match() {
+        use nom::character::complete::alpha1;
+
+        let result: IResult<_, _, LocatedError> = bounded_token(3, alpha1)(Located::new("abc!", 2));
+        assert_eq!(&*result.unwrap().1, "abc");
+        let result: IResult<_, _, LocatedError> =
+            bounded_token(3, alpha1)(Located::new("abcdef!", 2));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.kind, ErrorKind::TooLarge);
+                assert_eq!(error.span, 2..8);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn furthest_consumed_spans_progress() {
+        use nom::branch::alt;
+        use nom::bytes::complete::tag;
+        use nom::sequence::tuple;
+
+        let metrics = RefCell::new(Metrics::new());
+        let mut parser = furthest_consumed(
+            &metrics,
+            alt((
+                tuple((
+                    metered(&metrics, tag("a")),
+                    metered(&metrics, tag("b")),
+                    metered(&metrics, tag("c")),
+                )),
+                tuple((
+                    metered(&metrics, tag("a")),
+                    metered(&metrics, tag("x")),
+                    metered(&metrics, tag("y")),
+                )),
+            )),
+        );
+        let result: IResult<_, _, LocatedError> = parser(Located::new("abd", 2));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.kind, ErrorKind::Tag);
+                assert_eq!(error.span, 2..4);
+            }
+            _ => panic!(),
+        }
+        // Progress from the previous parse does not extend the span.
+        let result: IResult<_, _, LocatedError> = parser(Located::new("z", 0));
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.span, 0..0),
+            _ => panic!(),
+        }
+        assert_eq!(metrics.borrow().furthest(), Some(4));
+    }
+
+    #[test]
+    fn map_opt_located_spans_rejected_output() {
+        use nom::character::complete::digit1;
+
+        let mut parser = map_opt_located(digit1, |digits: Located<str>| digits.parse::<u8>().ok());
+        let result: IResult<_, _, NomError<Located<str>>> = parser(Located::new("300;", 2));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.code, ErrorKind::MapOpt);
+                assert_eq!(Range::from(error.input), 2..5);
+            }
+            _ => panic!(),
+        }
+        let result: IResult<_, _, NomError<Located<str>>> = parser(Located::new("30;", 2));
+        assert_eq!(result.unwrap().1, 30);
+    }
+
+    #[test]
+    fn with_node_id_is_stable() {
+        use nom::character::complete::alpha1;
+
+        let id = |kind, location| {
+            let result: IResult<_, _, LocatedError> =
+                with_node_id(kind, alpha1)(Located::new("ab", location));
+            result.unwrap().1 .0
+        };
+        assert_eq!(id("ident", 3), id("ident", 3));
+        assert_ne!(id("ident", 3), id("ident", 4));
+        assert_ne!(id("ident", 3), id("keyword", 3));
+    }
+
+    #[test]
+    fn strict_list_rejects_trailing_separator() {
+        use nom::character::complete::{char, digit1};
+
+        let mut parser = strict_list(char(','), digit1);
+        let result: IResult<_, _, LocatedError> = parser(Located::from("1,22,3;"));
+        let (remaining, items) = result.unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(&*remaining, ";");
+
+        let result: IResult<_, _, LocatedError> = parser(Located::from("1,22,;"));
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.span, 4..5),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn up_to_span_excludes_lookahead() {
+        use nom::bytes::complete::tag;
+
+        let result: IResult<_, _, LocatedError> = up_to_span(tag("*/"))(Located::new("ab*/c", 2));
+        let (remaining, (span, fragment)) = result.unwrap();
+        assert_eq!((span, &*fragment), (2..4, "ab"));
+        assert_eq!(&*remaining, "*/c");
+
+        let result: IResult<_, _, LocatedError> = up_to_span(tag("*/"))(Located::new("abc", 2));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.kind, ErrorKind::TakeUntil);
+                assert_eq!(error.span, 2..5);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn nested_stateful_forwards_location() {
+        use nom::character::complete::alpha1;
+
+        let input = Stateful::new(Stateful::new(Located::new("abc def", 5), 1u8), 2u16);
+        assert_eq!(input.location(), 5);
+        let result: IResult<_, _, LocatedError> = span(alpha1)(input);
+        let (remaining, ((start, len), output)) = result.unwrap();
+        assert_eq!((start, len), (5, 3));
+        assert_eq!(output.location(), 5);
+        assert_eq!(remaining.location(), 8);
+        assert_eq!(input.offset(&remaining), 3);
+        assert_eq!((remaining.state, remaining.data.state), (2, 1));
+
+        let result: IResult<_, _, LocatedError> = take_while_span(|c| c == ' ')(remaining);
+        assert_eq!(result.unwrap().1 .0, 8..9);
+    }
+
+    #[test]
+    fn heredoc_spans_body() {
+        use nom::bytes::complete::tag;
+        use nom::combinator::rest;
+
+        let result: IResult<_, _, LocatedError> =
+            heredoc("EOF", rest)(Located::from("<<EOF\nab\ncd\nEOF\n;"));
+        let (remaining, (span, body)) = result.unwrap();
+        assert_eq!(span, 6..12);
+        assert_eq!(&*body, "ab\ncd\n");
+        assert_eq!(&*remaining, "\n;");
+
+        let result: IResult<_, _, LocatedError> =
+            heredoc("EOF", tag("ab\n"))(Located::from("<<EOF\nab\ncd\nEOF\n;"));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.kind, ErrorKind::Eof);
+                assert_eq!(error.span, 9..12);
+            }
+            _ => panic!(),
+        }
+
+        let result: IResult<_, _, LocatedError> =
+            heredoc("EOF", rest)(Located::from("<<EOF\nab\nEOFX"));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.kind, ErrorKind::TakeUntil);
+                assert_eq!(error.span, 6..13);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn escaped_transform_mapped_locates_chars() {
+        use nom::branch::alt;
+        use nom::bytes::complete::tag;
+        use nom::character::complete::alpha1;
+        use nom::combinator::value;
+
+        let mut parser = escaped_transform_mapped(
+            alpha1,
+            '\\',
+            alt((value("\n", tag("n")), value("\\", tag("\\")))),
+        );
+        let result: IResult<_, _, LocatedError> = parser(Located::new(r"ab\ncd\\e", 10));
+        let (remaining, (decoded, locations)) = result.unwrap();
+        assert_eq!(decoded, "ab\ncd\\e");
+        assert_eq!(locations, [10, 11, 12, 14, 15, 16, 18]);
+        assert_eq!(remaining.location(), 19);
+    }
+
+    #[test]
+    fn classify_spans_covers_input() {
+        use nom::character::complete::{alpha1, space1};
+
+        let mut parser = classify_spans(alpha1, space1);
+        let result: IResult<_, _, LocatedError> = parser(Located::from("ab  cd"));
+        let spans = result.unwrap().1;
+        assert_eq!(
+            spans,
+            [
+                (0..2, Significance::Significant),
+                (2..4, Significance::Insignificant),
+                (4..6, Significance::Significant),
+            ],
+        );
+        let result: IResult<_, _, LocatedError> = parser(Located::from("ab !"));
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.location(), 3),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn soft_keyword_depends_on_state() {
+        let mut keywords = SoftKeywords::new();
+        let result: IResult<_, _, LocatedError> =
+            soft_keyword("match")(Stateful::new(Located::new("match x", 2), keywords.clone()));
+        assert!(result.is_err());
+
+        keywords.enable("match");
+        let result: IResult<_, _, LocatedError> =
+            soft_keyword("match")(Stateful::new(Located::new("match x", 2), keywords.clone()));
+        let (remaining, span) = result.unwrap();
+        assert_eq!(span, 2..7);
+        assert_eq!(remaining.location(), 7);
+        let result: IResult<_, _, LocatedError> =
+            soft_keyword("match")(Stateful::new(Located::new("matches", 2), keywords.clone()));
+        assert!(result.is_err());
+
+        keywords.disable("match");
+        assert!(!keywords.is_enabled("match"));
+    }
+
+    #[test]
+    fn completion_context_spans_partial_word() {
+        use nom::bytes::complete::tag;
+
+        let result: IResult<_, _, LocatedError> =
+            completion_context(tag("function"))(Located::new("func x", 3));
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.span, 3..7),
+            _ => panic!(),
+        }
+        let result: IResult<_, _, LocatedError> =
+            completion_context(tag("function"))(Located::new(" x", 3));
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.span, 3..3),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn validate_contiguity_finds_first_error() {
+        assert_eq!(validate_contiguity(&[0..2, 2..5], 5), Ok(()));
+        assert_eq!(
+            validate_contiguity(&[0..2, 3..5], 5),
+            Err(ContiguityError::Gap {
+                index: 1,
+                span: 2..3
+            }),
+        );
+        assert_eq!(
+            validate_contiguity(&[0..3, 2..5], 5),
+            Err(ContiguityError::Overlap {
+                index: 1,
+                span: 2..3
+            }),
+        );
+        assert_eq!(
+            validate_contiguity(&[0..1, 1..2], 4),
+            Err(ContiguityError::Gap {
+                index: 2,
+                span: 2..4
+            }),
+        );
+        assert_eq!(
+            validate_contiguity(&[0..2, 2..6], 5),
+            Err(ContiguityError::OutOfBounds {
+                index: 1,
+                span: 5..6
+            }),
+        );
+    }
+
+    #[test]
+    fn delimited_list_with_seps_spans_separators() {
+        use nom::character::complete::{char, digit1};
+
+        let mut parser = delimited_list_with_seps(char(','), digit1);
+        let result: IResult<_, _, LocatedError> = parser(Located::from("1,22,3,"));
+        let (remaining, (items, separators)) = result.unwrap();
+        let spans: Vec<_> = items.iter().map(|item| item.span.clone()).collect();
+        assert_eq!(spans, [0..1, 2..4, 5..6]);
+        assert_eq!(separators, [1..2, 4..5]);
+        assert_eq!(&*remaining, ",");
+
+        let result: IResult<_, _, LocatedError> = parser(Located::from(";"));
+        let (items, separators) = result.unwrap().1;
+        assert!(items.is_empty() && separators.is_empty());
+    }
+
+    #[test]
+    fn with_surrounding_ws_spans_whitespace() {
+        use nom::character::complete::alpha1;
+
+        let result: IResult<_, _, LocatedError> =
+            with_surrounding_ws(alpha1)(Located::from("  ab \n;"));
+        let (remaining, (leading, token, trailing)) = result.unwrap();
+        assert_eq!((leading, token.span, trailing), (0..2, 2..4, 4..6));
+        assert_eq!(&*remaining, ";");
+
+        let result: IResult<_, _, LocatedError> = with_surrounding_ws(alpha1)(Located::from("ab;"));
+        let (leading, token, trailing) = result.unwrap().1;
+        assert_eq!((leading, token.span, trailing), (0..0, 0..2, 2..2));
+    }
+
+    #[test]
+    fn signed_spanned_spans_sign() {
+        use nom::character::complete::digit1;
+
+        let parse = |text: &'static str| {
+            let result: IResult<_, _, LocatedError> = signed_spanned(digit1)(Located::new(text, 1));
+            let (sign, number) = result.unwrap().1;
+            (sign, number.span, number.value.into_data())
+        };
+        assert_eq!(parse("12"), (None, 1..3, "12"));
+        assert_eq!(parse("+12"), (Some(1..2), 2..4, "12"));
+        assert_eq!(parse("-3"), (Some(1..2), 2..3, "3"));
+    }
+
+    #[test]
+    fn offset_map_maps_to_original() {
+        let identity = OffsetMap::new();
+        assert_eq!(identity.original_offset(5), 5);
+
+        // `a&amp;b&lt;c` is transformed into `a&b<c`.
+        let mut map = OffsetMap::new();
+        map.push(1..2, 1..6);
+        map.push(3..4, 7..11);
+        let offsets: Vec<_> = (0..=5).map(|offset| map.original_offset(offset)).collect();
+        assert_eq!(offsets, [0, 1, 6, 7, 11, 12]);
+    }
+
+    #[test]
+    fn foldable_spans_lines_of_block() {
+        use nom::bytes::complete::is_not;
+        use nom::character::complete::char;
+
+        let source = "f {\n  a\n}\n";
+        let index = LineIndex::new(source);
+        let input = Located::from(source).take_split(2).0;
+        let result: IResult<_, _, LocatedError> =
+            foldable(&index, char('{'), is_not("}"), char('}'))(input);
+        let (remaining, block) = result.unwrap();
+        assert_eq!(block.span, 0..8);
+        assert_eq!(index.line_col(block.span.start).line, 0);
+        assert_eq!(index.line_col(block.span.end).line, 2);
+        assert_eq!(&*remaining, "\n");
+    }
+
+    #[test]
+    fn offside_rule_aligns_items() {
+        use nom::character::complete::{alpha1, multispace0};
+        use nom::multi::many1;
+        use nom::sequence::{preceded, terminated};
+
+        type Input<'i> = Stateful<Tracked<'i, str>, usize>;
+
+        fn item(input: Input) -> IResult<Input, Tracked<str>, LocatedError> {
+            preceded(offside_eq, terminated(alpha1, multispace0))(input)
+                .map(|(remaining, item)| (remaining, item.data))
+        }
+
+        let input = Stateful::new(Tracked::from("x:\n  ab\n  cd\n e"), 0);
+        let input = input.take_split(5).0;
+        let result = with_reference_column(many1(item))(input);
+        let (remaining, items) = result.unwrap();
+        let items: Vec<_> = items.iter().map(|item| &**item).collect();
+        assert_eq!(items, ["ab", "cd"]);
+        assert_eq!(remaining.state, 0);
+        assert_eq!(remaining.full_location(), (14, 3, 1));
+        // The misaligned item is offside of the block, but not the enclosing
+        // reference column.
+        let result: IResult<_, _, LocatedError> = offside_gt(remaining);
+        assert!(result.is_ok());
+        let result: IResult<_, _, LocatedError> = offside_eq(remaining);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn interned_gets_span_and_fragment() {
+        use nom::character::complete::alpha1;
+
+        let result: IResult<_, _, LocatedError> = interned(alpha1)(Located::new("abc1", 2));
+        let (remaining, (span, fragment, output)) = result.unwrap();
+        assert_eq!(span, 2..5);
+        assert_eq!(&*fragment, "abc");
+        assert_eq!(fragment.location(), 2);
+        assert_eq!(&*output, "abc");
+        assert_eq!(&*remaining, "1");
+    }
+
+    #[test]
+    fn separated_list_strict_locates_missing_separator() {
+        use nom::character::complete::{char, digit1, space0};
+        use nom::sequence::preceded;
+
+        let mut parser = separated_list_strict(char(','), preceded(space0, digit1));
+        let result: IResult<_, _, LocatedError> = parser(Located::from("1, 2;"));
+        let (remaining, items) = result.unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(&*remaining, ";");
+
+        let result: IResult<_, _, LocatedError> = parser(Located::from("1, 2 3"));
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.span, 4..4),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn opener_and_full_spans_nested_constructs() {
+        use nom::bytes::complete::tag;
+        use nom::character::complete::char;
+        use nom::combinator::opt;
+
+        type Spans = Vec<(Range<usize>, Range<usize>)>;
+
+        fn group(input: Located<str>) -> IResult<Located<str>, Spans, LocatedError> {
+            let (remaining, (opener, full, inner)) =
+                opener_and_full(tag("<<"), opt(group), char('>'))(input)?;
+            let mut spans = vec![(opener, full)];
+            spans.extend(inner.into_iter().flatten());
+            Ok((remaining, spans))
+        }
+
+        let (remaining, spans) = group(Located::from("<<<<>>;")).unwrap();
+        assert_eq!(spans, [(0..2, 0..6), (2..4, 2..5)]);
+        assert_eq!(&*remaining, ";");
+    }
+
+    #[test]
+    fn incomplete_frame_locates_frame() {
+        use nom::bytes::streaming::take;
+
+        let mut parser = incomplete_frame(take(4usize));
+        let result: IResult<_, _, ExternalError<IncompleteFrame>> =
+            parser(Located::new(&b"ab"[..], 6));
+        match result {
+            Err(ErrorMode::Error(ExternalError(Some(frame)))) => {
+                assert_eq!(frame.start, 6);
+                assert_eq!(frame.needed, Needed::new(2));
+            }
+            _ => panic!(),
+        }
+        let result: IResult<_, _, ExternalError<IncompleteFrame>> =
+            parser(Located::new(&b"abcd"[..], 6));
+        assert_eq!(result.unwrap().1.location(), 6);
+    }
+
+    #[test]
+    fn lines_of_spans_lines() {
+        use nom::character::complete::not_line_ending;
+
+        let result: IResult<_, _, LocatedError> =
+            lines_of(not_line_ending)(Located::from("ab\n\r\ncd"));
+        let (remaining, lines) = result.unwrap();
+        let spans: Vec<_> = lines.iter().map(|line| line.span.clone()).collect();
+        assert_eq!(spans, [0..2, 3..3, 5..7]);
+        assert_eq!(&*lines[2].value, "cd");
+        assert_eq!(remaining.input_len(), 0);
+
+        let result: IResult<_, _, LocatedError> = eol(Located::from(""));
+        assert_eq!(&*result.unwrap().1, "");
+    }
+
+    #[test]
+    fn span_digest_compares_content() {
+        let source = "abcabd";
+        assert_eq!(span_digest(source, 0..2), span_digest(source, 3..5));
+        assert_ne!(span_digest(source, 0..3), span_digest(source, 3..6));
+        assert_eq!(span_digest(source, 1..1), span_digest(source, 4..4));
+        assert_ne!(span_digest(source, 0..0), span_digest(source, 0..1));
+    }
+
+    #[test]
+    fn spanned_tuple_spans_components() {
+        use nom::character::complete::{char, digit1};
+        use nom::sequence::preceded;
+
+        let mut parser = spanned_tuple((
+            digit1,
+            preceded(char('-'), digit1),
+            preceded(char('-'), digit1),
+        ));
+        let result: IResult<_, _, LocatedError> = parser(Located::new("2024-01-15", 3));
+        let ((year, month, day), spans) = result.unwrap().1;
+        assert_eq!((&*year, &*month, &*day), ("2024", "01", "15"));
+        assert_eq!(spans, [3..7, 7..10, 10..13]);
+        assert_eq!(spans[0].start..spans[2].end, 3..13);
+    }
+
+    #[test]
+    fn furthest_alt_keeps_furthest_error() {
+        use nom::bytes::complete::tag;
+        use nom::combinator::recognize;
+        use nom::error::context;
+        use nom::sequence::tuple;
+
+        let mut parser = furthest_alt((
+            recognize(tuple((tag("a"), tag("x")))),
+            recognize(tuple((tag("a"), tag("b"), context("c", tag("c"))))),
+            recognize(tuple((tag("z"), tag("z")))),
+        ));
+        match parser(Located::from("abd")) {
+            Err(ErrorMode::Error(FurthestError(error))) => {
+                assert_eq!(error.location(), 2);
+                assert_eq!(error.context, [(2, "c")]);
+            }
+            _ => panic!(),
+        }
+
+        let mut parser = furthest_alt((context("first", tag("a")), context("second", tag("b"))));
+        match parser(Located::from("c")) {
+            Err(ErrorMode::Error(FurthestError(error))) => {
+                assert_eq!(error.location(), 0);
+                assert_eq!(error.context.len(), 2);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn with_attributes_spans_each_attribute() {
+        use nom::bytes::complete::tag;
+        use nom::character::complete::{alpha1, char};
+        use nom::sequence::delimited;
+
+        let mut parser = with_attributes(delimited(tag("#["), alpha1, char(']')), alpha1);
+        let mut parse = |text: &'static str| {
+            let result: IResult<_, _, LocatedError> = parser(Located::from(text));
+            let (attributes, item) = result.unwrap().1;
+            let attributes: Vec<_> = attributes
+                .into_iter()
+                .map(|attribute| (attribute.value.into_data(), attribute.span))
+                .collect();
+            (attributes, item.span)
+        };
+        assert_eq!(parse("fn"), (vec![], 0..2));
+        assert_eq!(parse("#[a]fn"), (vec![("a", 0..4)], 4..6));
+        assert_eq!(
+            parse("#[a]#[bc]fn"),
+            (vec![("a", 0..4), ("bc", 4..9)], 9..11)
+        );
+    }
+
+    #[test]
+    fn recover_to_sync_skips_to_sync() {
+        use nom::character::complete::{char, digit1};
+        use nom::sequence::terminated;
+
+        let mut parser = recover_to_sync(terminated(digit1, char(';')), |c| c == ';');
+        let result: IResult<_, _, LocatedError> = parser(Located::from("12;"));
+        assert_eq!(result.unwrap().1.map(|digits| digits.into_data()), Ok("12"));
+
+        let result: IResult<_, _, LocatedError> = parser(Located::from("1x2;3;"));
+        let (remaining, output) = result.unwrap();
+        assert_eq!(output.map(|_| ()), Err(0..3));
+        assert_eq!(&*remaining, ";3;");
+
+        let result: IResult<_, _, LocatedError> = parser(Located::from("x"));
+        assert_eq!(result.unwrap().1.map(|_| ()), Err(0..1));
+    }
+
+    #[test]
+    fn line_header_gets_span_and_indent() {
+        let source = "a\n  b\n\t c\n";
+        let index = LineIndex::new(source);
+        let header = |location: usize| {
+            let input = Located::from(source).take_split(location).0;
+            let result: IResult<_, _, LocatedError> = line_header(&index, 4)(input);
+            result.map(|(remaining, header)| {
+                assert_eq!(remaining.location(), location);
+                header
+            })
+        };
+        assert_eq!(header(0).unwrap(), (0..2, 0));
+        assert_eq!(header(2).unwrap(), (2..6, 2));
+        assert_eq!(header(6).unwrap(), (6..10, 5));
+        assert!(header(1).is_err());
+    }
+
+    #[test]
+    fn decimal_parts_spanned_spans_parts() {
+        let parse = |text: &'static str| {
+            let result: IResult<_, _, LocatedError> = decimal_parts_spanned(Located::new(text, 1));
+            let (integer, fraction) = result.unwrap().1;
+            (
+                (integer.span, integer.value.into_data()),
+                fraction.map(|fraction| (fraction.span, fraction.value.into_data())),
+            )
+        };
+        assert_eq!(parse("12"), ((1..3, "12"), None));
+        assert_eq!(parse("12.50x"), ((1..3, "12"), Some((4..6, "50"))));
+        assert_eq!(parse("12."), ((1..3, "12"), Some((4..4, ""))));
+    }
+
+    #[test]
+    fn parent_span_merges_children() {
+        let child = |span| Spanned::new(span, ());
+        assert_eq!(parent_span::<()>(&[], 7), 7..7);
+        assert_eq!(parent_span(&[child(2..4)], 7), 2..4);
+        assert_eq!(
+            parent_span(&[child(4..6), child(1..2), child(3..9)], 0),
+            1..9
+        );
+    }
+
+    #[test]
+    fn skip_trivia_spans_comments() {
+        let style = CommentStyle {
+            line: Some("//"),
+            block: Some(("/*", "*/")),
+            nested: true,
+        };
+        let result: IResult<_, _, LocatedError> =
+            skip_trivia(style)(Located::from(" // a\n /* b /* c */ */ x"));
+        let (remaining, span) = result.unwrap();
+        assert_eq!(span, 0..23);
+        assert_eq!(&*remaining, "x");
+
+        let style = CommentStyle {
+            nested: false,
+            ..style
+        };
+        let result: IResult<_, _, LocatedError> =
+            skip_trivia(style)(Located::from("/* a /* b */ c */"));
+        let (remaining, span) = result.unwrap();
+        assert_eq!(span, 0..13);
+        assert_eq!(&*remaining, "c */");
+
+        let result: IResult<_, _, LocatedError> = skip_trivia(style)(Located::from("x"));
+        assert_eq!(result.unwrap().1, 0..0);
+        let result: IResult<_, _, LocatedError> = skip_trivia(style)(Located::from(" /* a"));
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.span, 1..5),
+            _ => panic!(),
+        }
+
+        let style = CommentStyle {
+            line: Some("#"),
+            ..CommentStyle::default()
+        };
+        let result: IResult<_, _, LocatedError> = skip_trivia(style)(Located::from("# a\nx"));
+        assert_eq!(result.unwrap().1, 0..4);
+    }
+
+    #[test]
+    fn with_doc_comment_spans_doc() {
+        use nom::bytes::complete::tag;
+        use nom::character::complete::{alpha1, line_ending, not_line_ending};
+        use nom::sequence::{preceded, terminated};
+
+        let mut parser = with_doc_comment(
+            terminated(preceded(tag("///"), not_line_ending), line_ending),
+            alpha1,
+        );
+        let result: IResult<_, _, LocatedError> = parser(Located::from("/// doc\nitem"));
+        let (doc, item) = result.unwrap().1;
+        let doc = doc.unwrap();
+        assert_eq!((doc.span, &*doc.value), (0..8, " doc"));
+        assert_eq!(item.span, 8..12);
+
+        let result: IResult<_, _, LocatedError> = parser(Located::from("item"));
+        let (doc, item) = result.unwrap().1;
+        assert!(doc.is_none());
+        assert_eq!(item.span, 0..4);
+    }
+
+    #[test]
+    fn unexpected_spans_next_token() {
+        use nom::branch::alt;
+        use nom::bytes::complete::tag;
+
+        let mut parser = alt((tag("let"), tag("fn"), unexpected(&["let", "fn"])));
+        let result: IResult<_, _, ExternalError<Unexpected>> = parser(Located::new("match x", 3));
+        match result {
+            Err(ErrorMode::Error(ExternalError(Some(unexpected)))) => {
+                assert_eq!(unexpected.token, "match");
+                assert_eq!(unexpected.span, 3..8);
+                assert_eq!(unexpected.expected, ["let", "fn"]);
+            }
+            _ => panic!(),
+        }
+        let result: IResult<_, _, ExternalError<Unexpected>> = parser(Located::new("+1", 3));
+        match result {
+            Err(ErrorMode::Error(ExternalError(Some(unexpected)))) => {
+                assert_eq!((unexpected.token.as_str(), unexpected.span), ("+", 3..4));
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn capture_region_reparses_in_original_coordinates() {
+        use nom::bytes::complete::is_not;
+        use nom::character::complete::{alpha1, char, space1};
+        use nom::sequence::{delimited, tuple};
+
+        let mut parser = capture_region(delimited(char('('), is_not(")"), char(')')));
+        let result: IResult<_, _, LocatedError> = parser(Located::new("(ab cd) x", 10));
+        let (remaining, (_, region)) = result.unwrap();
+        assert_eq!((&*region, region.location()), ("(ab cd)", 10));
+        assert_eq!(&*remaining, " x");
+
+        let result: IResult<_, _, LocatedError> =
+            tuple((char('('), alpha1, space1, alpha1))(region);
+        let (_, (_, first, _, second)) = result.unwrap();
+        assert_eq!((first.location(), second.location()), (11, 14));
+    }
+
+    #[test]
+    fn permutation_spanned_spans_in_parse_order() {
+        use nom::bytes::complete::tag;
+
+        let mut parser = permutation_spanned((tag("a"), tag("bb"), tag("c")));
+        let result: IResult<_, _, LocatedError> = parser(Located::from("bbca"));
+        let ((a, b, c), spans) = result.unwrap().1;
+        assert_eq!((a.location(), b.location(), c.location()), (3, 0, 2));
+        assert_eq!(spans, [0..2, 2..3, 3..4]);
+
+        let result: IResult<_, _, LocatedError> = parser(Located::from("abbc"));
+        assert_eq!(result.unwrap().1 .1, [0..1, 1..3, 3..4]);
+        let result: IResult<_, _, LocatedError> = parser(Located::from("abbx"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn to_end_of_line_span_excludes_terminator() {
+        let input = Located::from("#include a b\r\nx").take_split(9).0;
+        let result: IResult<_, _, LocatedError> = to_end_of_line_span(input);
+        let (remaining, (span, line)) = result.unwrap();
+        assert_eq!(span, 9..12);
+        assert_eq!(&*line, "a b");
+        assert_eq!(&*remaining, "\r\nx");
+
+        let input = Located::from("a\nlast").take_split(2).0;
+        let result: IResult<_, _, LocatedError> = to_end_of_line_span(input);
+        let (remaining, (span, line)) = result.unwrap();
+        assert_eq!((span, &*line), (2..6, "last"));
+        assert_eq!(remaining.input_len(), 0);
+    }
+
+    #[test]
+    fn separated_list1_whole_span_spans_items() {
+        use nom::bytes::complete::tag;
+        use nom::character::complete::{char, digit1};
+        use nom::sequence::terminated;
+
+        let mut parser = separated_list1_whole_span(terminated(char(','), tag(" ")), digit1);
+        let result: IResult<_, _, LocatedError> = parser(Located::new("12 ", 1));
+        let (remaining, (span, items)) = result.unwrap();
+        assert_eq!((span, items.len()), (1..3, 1));
+        assert_eq!(&*remaining, " ");
+
+        let result: IResult<_, _, LocatedError> = parser(Located::new("1, 23, 4, ;", 1));
+        let (remaining, (span, items)) = result.unwrap();
+        assert_eq!(span, 1..9);
+        assert_eq!(
+            items.iter().map(|item| &**item).collect::<Vec<_>>(),
+            ["1", "23", "4"]
+        );
+        assert_eq!(&*remaining, ", ;");
+
+        let result: IResult<_, _, LocatedError> = parser(Located::new(", 1", 1));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn within_window_reparses_in_original_coordinates() {
+        use nom::character::complete::{alpha1, space1};
+        use nom::sequence::separated_pair;
+
+        let input = Located::new("fn ab cd; fn", 10);
+        let mut parser = within_window(13, 18, separated_pair(alpha1, space1, alpha1));
+        let result: IResult<_, _, LocatedError> = parser(input);
+        let (remaining, (first, second)) = result.unwrap();
+        assert_eq!((first.location(), second.location()), (13, 16));
+        assert_eq!((&*remaining, remaining.location()), ("; fn", 18));
+
+        let mut parser = within_window(13, 19, separated_pair(alpha1, space1, alpha1));
+        let result: IResult<_, _, LocatedError> = parser(input);
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.span, 18..19),
+            _ => panic!(),
+        }
+
+        let mut parser = within_window(5, 12, alpha1);
+        let result: IResult<_, _, LocatedError> = parser(input);
+        assert!(result.is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn emit_span_writes_json_lines() {
+        use nom::character::complete::{alpha1, char, digit1};
+        use nom::sequence::separated_pair;
+
+        let sink = RefCell::new(SpanSink::new(Vec::new()));
+        let mut parser = emit_span(
+            &sink,
+            "pair",
+            separated_pair(
+                emit_span(&sink, "key", alpha1),
+                char('='),
+                emit_span(&sink, "value", digit1),
+            ),
+        );
+        let result: IResult<_, _, LocatedError> = parser(Located::new("ab=12", 4));
+        assert!(result.is_ok());
+        drop(parser);
+        let lines = String::from_utf8(sink.into_inner().into_inner().unwrap()).unwrap();
+        assert_eq!(
+            lines,
+            concat!(
+                "{\"kind\":\"key\",\"start\":4,\"end\":6}\n",
+                "{\"kind\":\"value\",\"start\":7,\"end\":9}\n",
+                "{\"kind\":\"pair\",\"start\":4,\"end\":9}\n",
+            ),
+        );
+    }
+
+    #[test]
+    fn ignored_span_discards_output() {
+        use nom::bytes::complete::tag;
+        use nom::character::complete::space1;
+        use nom::sequence::tuple;
+
+        let result: IResult<_, _, LocatedError> =
+            tuple((tag("a"), ignored_span(space1), tag("b")))(Located::from("a   b"));
+        let (_, (_, span, _)): (_, (_, Range<usize>, _)) = result.unwrap();
+        assert_eq!(span, 1..4);
+        assert!(coverage(5, &[0..1, span, 4..5]).is_empty());
+    }
+
+    #[test]
+    fn verify_spanned_spans_rejected_value() {
+        use nom::character::complete::{char, u8};
+        use nom::sequence::preceded;
+
+        let mut parser = preceded(char(':'), verify_spanned(u8, |n| *n < 100));
+        let result: IResult<_, _, ExternalError<Spanned<u8>>> = parser(Located::new(":42", 3));
+        assert_eq!(result.unwrap().1, 42);
+        let result: IResult<_, _, ExternalError<Spanned<u8>>> = parser(Located::new(":200;", 3));
+        match result {
+            Err(ErrorMode::Error(ExternalError(Some(rejected)))) => {
+                assert_eq!(rejected, Spanned::new(4..7, 200));
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn symbol_table_records_definition_and_references() {
+        use nom::bytes::complete::tag;
+        use nom::character::complete::{alpha1, char, space0};
+        use nom::multi::many0;
+        use nom::sequence::{delimited, preceded, terminated};
+
+        let input = Stateful::new(Located::from("let x; x; y; x;"), SymbolTable::new());
+        let mut parser = preceded(
+            terminated(preceded(tag("let "), define(alpha1)), char(';')),
+            many0(delimited(space0, reference(alpha1), char(';'))),
+        );
+        let result: IResult<_, _, LocatedError> = parser(input);
+        let (remaining, references) = result.unwrap();
+        assert_eq!(references.len(), 3);
+        let symbols = remaining.state;
+        let x = symbols.get("x").unwrap();
+        assert_eq!(x.definition, Some(4..5));
+        assert_eq!(x.references, [7..8, 13..14]);
+        let y = symbols.get("y").unwrap();
+        assert_eq!(y.definition, None);
+        assert_eq!(y.references.len(), 1);
+        assert_eq!(y.references[0], 10..11);
+        assert!(symbols.get("z").is_none());
+    }
+
+    #[test]
+    fn ascii_only_locates_non_ascii_byte() {
+        use nom::bytes::complete::is_not;
+        use nom::character::complete::alpha1;
+
+        let result: IResult<_, _, LocatedError> = ascii_only(alpha1)(Located::new("abc é", 2));
+        assert_eq!(&*result.unwrap().1, "abc");
+
+        let result: IResult<_, _, LocatedError> = ascii_only(is_not(";"))(Located::new("ab é;", 2));
+        match result {
+            Err(ErrorMode::Error(error)) => assert_eq!(error.span, 5..6),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn report_emits_diagnostics() {
+        use nom::character::complete::{alpha1, char, digit1};
+        use nom::sequence::separated_pair;
+
+        #[derive(Default)]
+        struct Entries(Vec<(Range<usize>, Severity, String)>);
+
+        impl Diagnostics for Entries {
+            fn emit(&mut self, span: Range<usize>, severity: Severity, message: &str) {
+                self.0.push((span, severity, message.to_owned()));
+            }
+        }
+
+        let diagnostics = RefCell::new(Entries::default());
+        let mut parser = separated_pair(
+            report(&diagnostics, Severity::Note, "key", alpha1),
+            char('='),
+            report(&diagnostics, Severity::Error, "expected digits", digit1),
+        );
+        let result: IResult<_, _, LocatedError> = parser(Located::from("ab=12"));
+        assert!(result.is_ok());
+        let result: IResult<_, _, LocatedError> = parser(Located::from("c=x"));
+        assert!(result.is_err());
+        drop(parser);
+        assert_eq!(
+            diagnostics.into_inner().0,
+            [
+                (0..2, Severity::Note, "key".to_owned()),
+                (3..5, Severity::Error, "expected digits".to_owned()),
+                (0..1, Severity::Note, "key".to_owned()),
+                (2..2, Severity::Error, "expected digits".to_owned()),
+            ],
+        );
+    }
+
+    #[test]
+    fn enclosing_span_spans_offsets() {
+        assert_eq!(enclosing_span(&[], 10), None);
+        assert_eq!(enclosing_span(&[4], 10), Some(4..5));
+        assert_eq!(enclosing_span(&[7, 2, 5], 10), Some(2..8));
+        assert_eq!(enclosing_span(&[3, 12], 10), Some(3..10));
+    }
+
+    #[test]
+    fn contains_cursor_includes_boundaries() {
+        use nom::character::complete::alpha1;
+
+        let contains = |cursor| {
+            let result: IResult<_, _, LocatedError> =
+                contains_cursor(cursor, alpha1)(Located::new("abc ", 2));
+            let (contains, word) = result.unwrap().1;
+            assert_eq!(word.span, 2..5);
+            contains
+        };
+        assert!(contains(3));
+        assert!(contains(2));
+        assert!(contains(5));
+        assert!(!contains(1));
+        assert!(!contains(6));
+    }
+
+    #[test]
+    fn span_report_renders_snapshot() {
+        use nom::character::complete::{alpha1, char, digit1};
+        use nom::multi::separated_list1;
+        use nom::sequence::separated_pair;
+
+        let input = Stateful::new(Located::from("a=1,bc=23"), SpanReport::new());
+        let mut parser = record_node(
+            "list",
+            separated_list1(
+                char(','),
+                record_node(
+                    "pair",
+                    separated_pair(
+                        record_node("value", alpha1),
+                        char('='),
+                        record_node("key", digit1),
+                    ),
+                ),
+            ),
+        );
+        let result: IResult<_, _, LocatedError> = parser(input);
+        let report = result.unwrap().0.state;
+        assert_eq!(report.iter().count(), 7);
+        assert_eq!(
+            report.to_snapshot_string(),
+            concat!(
+                "0..9 list\n",
+                "0..3 pair\n",
+                "0..1 value\n",
+                "2..3 key\n",
+                "4..9 pair\n",
+                "4..6 value\n",
+                "7..9 key\n",
+            ),
+        );
+    }
+
+    #[test]
+    fn recursion_guard_detects_left_recursion() {
+        use nom::branch::alt;
+        use nom::character::complete::{char, digit1};
+        use nom::combinator::{map, recognize};
+        use nom::sequence::tuple;
+
+        type Input<'i> = Stateful<Located<'i, str>, Vec<(&'static str, usize)>>;
+        type Error = ExternalError<LeftRecursion>;
+
+        fn expr(input: Input) -> IResult<Input, (), Error> {
+            recursion_guard(
+                "expr",
+                alt((
+                    map(tuple((expr, char('+'), digit1)), |_| ()),
+                    map(digit1, |_| ()),
+                )),
+            )(input)
+        }
+
+        fn term(input: Input) -> IResult<Input, Input, Error> {
+            recursion_guard("term", recognize(tuple((digit1, char('+'), digit1))))(input)
+        }
+
+        let input = Stateful::new(Located::from("= 1+2"), Vec::new())
+            .take_split(2)
+            .0;
+        match expr(input.clone()) {
+            Err(ErrorMode::Failure(ExternalError(Some(recursion)))) => {
+                assert_eq!(
+                    recursion,
+                    LeftRecursion {
+                        rule: "expr",
+                        location: 2,
+                    },
+                );
+            }
+            _ => panic!(),
+        }
+        let (remaining, _) = term(input).unwrap();
+        assert!(remaining.state.is_empty());
+    }
+
+    #[test]
+    fn dual_span_agrees_with_line_index() {
+        use nom::bytes::complete::is_not;
+
+        let source = "a\nbc {\nd}\n";
+        let index = LineIndex::new(source);
+        let input = Located::from(source).take_split(5).0;
+        let result: IResult<_, _, LocatedError> = dual_span(&index, is_not("}"))(input);
+        let (span, (start, end), fragment) = result.unwrap().1;
+        assert_eq!(span, 5..8);
+        assert_eq!(&*fragment, "{\nd");
+        assert_eq!((start, end), (index.line_col(5), index.line_col(8)));
+        assert_eq!(start, LineColumn { line: 1, column: 3 });
+        assert_eq!(end, LineColumn { line: 2, column: 1 });
+    }
+
+    #[test]
+    fn many1_progress_rejects_zero_width_parser() {
+        use nom::character::complete::{alpha0, alpha1, space0};
+        use nom::sequence::terminated;
+
+        let mut parser = many1_progress(terminated(alpha1, space0));
+        let result: IResult<_, _, LocatedError> = parser(Located::from("ab cd;"));
+        let (remaining, words) = result.unwrap();
+        assert_eq!(words.len(), 2);
+        assert_eq!(&*remaining, ";");
+        let result: IResult<_, _, LocatedError> = parser(Located::from(";"));
+        assert!(matches!(result, Err(ErrorMode::Error(_))));
+
+        let mut parser = many1_progress(alpha0);
+        let result: IResult<_, _, LocatedError> = parser(Located::from("ab;"));
+        match result {
+            Err(ErrorMode::Error(error)) => {
+                assert_eq!(error.span, 2..2);
+                assert_eq!(error.kind, ErrorKind::Many1);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[cfg(feature = "codegen")]
+    #[test]
+    fn blame_map_gets_smallest_generated_span() {
+        let mut blame = BlameMap::new();
+        blame.insert(0..20, 100..150);
+        blame.insert(4..8, 110..112);
+        blame.insert(30..35, 160..170);
+        assert_eq!(blame.blame(5), Some(110..112));
+        assert_eq!(blame.blame(4), Some(110..112));
+        assert_eq!(blame.blame(8), Some(100..150));
+        assert_eq!(blame.blame(0), Some(100..150));
+        assert_eq!(blame.blame(34), Some(160..170));
+        assert_eq!(blame.blame(20), None);
+        assert_eq!(blame.blame(35), None);
+    }
+
+    #[test]
+    fn delimiter_pair_spans_matches_nested_pairs() {
+        use nom::character::complete::{char, space0};
+        use nom::combinator::opt;
+        use nom::sequence::delimited;
+
+        type Pairs = Vec<(Range<usize>, Range<usize>)>;
+
+        fn block(input: Located<str>) -> IResult<Located<str>, Pairs, LocatedError> {
+            let inner = delimited(space0, opt(block), space0);
+            let (remaining, (open, close, inner)) =
+                delimiter_pair_spans(char('{'), inner, char('}'))(input)?;
+            let mut pairs = vec![(open, close)];
+            pairs.extend(inner.into_iter().flatten());
+            Ok((remaining, pairs))
+        }
+
+        let (_, pairs) = block(Located::from("{ {{}} }")).unwrap();
+        assert_eq!(pairs, [(0..1, 7..8), (2..3, 5..6), (3..4, 4..5)]);
+        assert!(block(Located::from("{ {}")).is_err());
+    }
+
+    #[test]
+    fn observe_gives_nodes_in_order() {
+        use nom::character::complete::{alpha1, char, digit1};
+        use nom::sequence::separated_pair;
+
+        #[derive(Default)]
+        struct Counter {
+            count: usize,
+            nodes: Vec<(&'static str, Range<usize>)>,
+        }
+
+        impl SpanObserver for Counter {
+            fn on_node(&mut self, kind: &'static str, span: Range<usize>) {
+                self.count += 1;
+                self.nodes.push((kind, span));
+            }
+        }
+
+        let observer = RefCell::new(Counter::default());
+        let mut parser = observe(
+            &observer,
+            "pair",
+            separated_pair(
+                observe(&observer, "key", alpha1),
+                char('='),
+                observe(&observer, "value", digit1),
+            ),
+        );
+        let result: IResult<_, _, LocatedError> = parser(Located::from("ab=1"));
+        assert!(result.is_ok());
+        let result: IResult<_, _, LocatedError> = parser(Located::from("c=x"));
+        assert!(result.is_err());
+        drop(parser);
+        let observer = observer.into_inner();
+        assert_eq!(observer.count, 4);
+        assert_eq!(
+            observer.nodes,
+            [
+                ("key", 0..2),
+                ("value", 3..4),
+                ("pair", 0..4),
+                ("key", 0..1)
+            ]
+        );
+    }
+
+    #[test]
+    fn single_line_span_gets_columns_of_line() {
+        use nom::bytes::complete::{is_not, take};
+
+        let source = "ab\ncd ef\ng";
+        let index = LineIndex::new(source);
+        let parse = |location: usize, n: usize| {
+            let input = Located::from(source).take_split(location).0;
+            let result: IResult<_, _, LocatedError> = single_line_span(&index, take(n))(input);
+            result.unwrap().1 .0
+        };
+        assert_eq!(parse(6, 2), Some((1, 3..5)));
+        assert_eq!(parse(3, 5), Some((1, 0..5)));
+        assert_eq!(parse(9, 0), Some((2, 0..0)));
+        assert_eq!(parse(1, 4), None);
+        assert_eq!(parse(3, 6), None);
+
+        let input = Located::from(source).take_split(3).0;
+        let result: IResult<_, _, LocatedError> = single_line_span(&index, is_not("\n"))(input);
+        assert_eq!(result.unwrap().1 .0, Some((1, 0..5)));
     }
 }
@@ -0,0 +1,139 @@
+//! Helpers for testing located parsers.
+//!
+//! These helpers are intended for the test suites of crates that use Pori.
+//! Enable the `testing` feature for development dependencies only:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! pori = { version = "...", features = ["testing"] }
+//! ```
+//!
+//! The helpers accept any located input, such as `Located`, `Stateful`, or
+//! `Tracked`, and panic with a descriptive message when an assertion fails.
+
+use nom::{Compare, CompareResult, IResult, InputLength, Parser};
+use std::fmt::Debug;
+use std::ops::Range;
+
+use crate::{Located, Location};
+
+/// Locates input at zero.
+pub fn located<I>(input: &I) -> Located<'_, I>
+where
+    I: ?Sized,
+{
+    Located::from(input)
+}
+
+/// Asserts that a parser succeeds over `input` and consumes the given span.
+#[track_caller]
+pub fn assert_span<I, O, E, F>(mut parser: F, input: I, expected: Range<usize>)
+where
+    I: Location,
+    E: Debug,
+    F: Parser<I, O, E>,
+{
+    let start = input.location();
+    match parser.parse(input) {
+        Ok((remaining, _)) => {
+            let span = start..remaining.location();
+            assert_eq!(span, expected, "unexpected span");
+        }
+        Err(error) => panic!("parser failed: {:?}", error),
+    }
+}
+
+/// Asserts that a parse succeeded and that the given input remains.
+#[track_caller]
+pub fn assert_remaining<I, O, E, T>(result: IResult<I, O, E>, expected: T)
+where
+    I: Compare<T> + Debug + InputLength,
+    E: Debug,
+    T: Clone + Debug + InputLength,
+{
+    match result {
+        Ok((remaining, _)) => {
+            let is_expected = remaining.input_len() == expected.input_len()
+                && remaining.compare(expected.clone()) == CompareResult::Ok;
+            assert!(
+                is_expected,
+                "unexpected remaining input: expected {:?}, found {:?}",
+                expected, remaining,
+            );
+        }
+        Err(error) => panic!("parser failed: {:?}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nom::bytes::complete::tag;
+    use nom::error::Error as NomError;
+    use nom::IResult;
+
+    use crate::testing::{assert_remaining, assert_span, located};
+    use crate::{Located, Location, Stateful, Tracked};
+
+    #[test]
+    fn located_at_zero() {
+        let input = located("abc");
+        assert_eq!(input.location(), 0);
+        assert_eq!(&*input, "abc");
+
+        let input = located(b"abc".as_ref());
+        assert_eq!(input.location(), 0);
+        assert_eq!(&*input, b"abc");
+    }
+
+    #[test]
+    fn assert_span_over_inputs() {
+        assert_span(tag::<_, _, NomError<_>>("ab"), located("abc"), 0..2);
+        assert_span(
+            tag::<_, _, NomError<_>>("ab"),
+            Located::new(&"xabc"[1..], 1),
+            1..3,
+        );
+        assert_span(
+            tag::<_, _, NomError<_>>("ab"),
+            Stateful::new(located("abc"), ()),
+            0..2,
+        );
+        assert_span(tag::<_, _, NomError<_>>("ab"), Tracked::from("abc"), 0..2);
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected span")]
+    fn assert_span_with_unexpected_span() {
+        assert_span(tag::<_, _, NomError<_>>("ab"), located("abc"), 0..3);
+    }
+
+    #[test]
+    #[should_panic(expected = "parser failed")]
+    fn assert_span_with_failed_parser() {
+        assert_span(tag::<_, _, NomError<_>>("x"), located("abc"), 0..1);
+    }
+
+    #[test]
+    fn assert_remaining_over_inputs() {
+        let result: IResult<_, _, NomError<_>> = tag("ab")(located("abc"));
+        assert_remaining(result, "c");
+        let result: IResult<_, _, NomError<_>> = tag("ab")(Stateful::new(located("abc"), ()));
+        assert_remaining(result, "c");
+        let result: IResult<_, _, NomError<_>> = tag("abc")(located("abc"));
+        assert_remaining(result, "");
+    }
+
+    #[test]
+    #[should_panic(expected = "unexpected remaining input")]
+    fn assert_remaining_with_unexpected_input() {
+        let result: IResult<_, _, NomError<_>> = tag("a")(located("abc"));
+        assert_remaining(result, "b");
+    }
+
+    #[test]
+    #[should_panic(expected = "parser failed")]
+    fn assert_remaining_with_failed_parser() {
+        let result: IResult<_, _, NomError<_>> = tag("x")(located("abc"));
+        assert_remaining(result, "bc");
+    }
+}